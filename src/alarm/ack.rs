@@ -0,0 +1,557 @@
+use crate::alarm::runtime::AlarmRuntime;
+use crate::alarm::suppression::matches;
+use crate::alarm::{
+    AlarmAck, AlarmSeverity, Definitions, DigitalAlarm, Emitter, IsaEvent, IsaState, Notice,
+};
+use async_trait::async_trait;
+use cache::Cache;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+/// Configuration of the discrete alarms, served by the `Cache`.
+#[async_trait]
+pub trait AlarmConfigs: Send + Sync {
+    async fn get_alm_config(&self, name: &str) -> Option<DigitalAlarm>;
+}
+
+#[async_trait]
+impl AlarmConfigs for Cache {
+    async fn get_alm_config(&self, name: &str) -> Option<DigitalAlarm> {
+        Cache::get_alm_config(self, name).await
+    }
+}
+
+/// Ack received on the ack exchange. Older clients send only the name of
+/// the alarm as plain text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct AckRequest {
+    /// Name of the alarm. A glob with `*`, or a prefix ending with `/`, acks
+    /// every configured alarm matching it.
+    pub alarm: String,
+
+    #[serde(default)]
+    pub user: Option<String>,
+
+    #[serde(default)]
+    pub comment: Option<String>,
+
+    /// When the operator acked the alarm on the client.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    /// Only ack the alarms with this severity.
+    #[serde(default)]
+    pub severity: Option<AlarmSeverity>,
+
+    /// Only ack the alarms whose condition is still active, or gone.
+    #[serde(default)]
+    pub active: Option<bool>,
+}
+
+impl AckRequest {
+    pub fn parse(payload: &str) -> Result<Self, serde_json::Error> {
+        let payload = payload.trim();
+        if payload.starts_with('{') {
+            return serde_json::from_str(payload);
+        }
+
+        Ok(Self {
+            alarm: payload.to_string(),
+            user: None,
+            comment: None,
+            timestamp: None,
+            severity: None,
+            active: None,
+        })
+    }
+
+    pub fn is_bulk(&self) -> bool {
+        self.alarm.contains('*') || self.alarm.ends_with('/')
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        if self.alarm.ends_with('/') {
+            name.starts_with(&self.alarm)
+        } else {
+            matches(&self.alarm, name)
+        }
+    }
+}
+
+/// Outcome of the ack of a single alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AckOutcome {
+    Acked,
+    /// The alarm was already acknowledged.
+    AlreadyAcked,
+    /// The alarm was never set.
+    NotActive,
+    /// The alarm is shelved, suppressed or out of service.
+    NotAllowed,
+    /// The alarm isn't configured.
+    Unknown,
+}
+
+/// Acks the alarm in memory. An alarm with nothing to ack is left as it is.
+pub fn apply_ack(runtime: &mut AlarmRuntime) -> AckOutcome {
+    match runtime.machine.state() {
+        IsaState::Unack | IsaState::RtnUnack => {}
+        IsaState::Acked => return AckOutcome::AlreadyAcked,
+        IsaState::Normal => {
+            // Back to normal after being acked, or never set at all
+            let acked = runtime
+                .last
+                .as_ref()
+                .is_some_and(|last| last.ack == AlarmAck::Ack);
+            return if acked {
+                AckOutcome::AlreadyAcked
+            } else {
+                AckOutcome::NotActive
+            };
+        }
+        _ => return AckOutcome::NotAllowed,
+    }
+
+    if runtime.machine.handle(IsaEvent::Ack).is_err() {
+        return AckOutcome::NotAllowed;
+    }
+    if let Some(last) = &mut runtime.last {
+        last.ack = AlarmAck::Ack;
+    }
+    AckOutcome::Acked
+}
+
+pub async fn process_ack(mut rx_ack: mpsc::Receiver<String>, emitter: Emitter, cache: Cache) {
+    while let Some(payload) = rx_ack.recv().await {
+        let ack = match AckRequest::parse(&payload) {
+            Ok(ack) => ack,
+            Err(e) => {
+                eprintln!("Invalid ack '{payload}' - {e}");
+                continue;
+            }
+        };
+
+        if ack.is_bulk() {
+            ack_bulk(&emitter, &cache, &ack).await;
+        } else {
+            let outcome = ack_alarm(&emitter, &cache, &ack, &ack.alarm).await;
+            if !matches!(outcome, AckOutcome::Acked | AckOutcome::Unknown) {
+                emitter
+                    .notify(Notice::AckIgnored {
+                        alarm: ack.alarm.clone(),
+                        timestamp: Utc::now(),
+                        reason: outcome,
+                    })
+                    .await;
+            }
+        }
+    }
+}
+
+/// Configured alarms matching the bulk ack and waiting for an ack, with the
+/// severity and the condition it asks for.
+pub fn bulk_targets(
+    ack: &AckRequest,
+    definitions: &Definitions,
+    alarms: &HashMap<String, AlarmRuntime>,
+) -> Vec<String> {
+    let mut names: Vec<String> = definitions
+        .names()
+        .filter(|name| ack.matches(name))
+        .filter(|name| {
+            let Some(runtime) = alarms.get(name) else {
+                return false;
+            };
+            let waiting = matches!(
+                runtime.machine.state(),
+                IsaState::Unack | IsaState::RtnUnack
+            );
+            waiting
+                && ack
+                    .active
+                    .is_none_or(|active| runtime.machine.is_active() == active)
+                && ack
+                    .severity
+                    .as_ref()
+                    .is_none_or(|severity| runtime.severity_is(severity))
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// Acks every configured alarm matching the request and waiting for an ack,
+/// then publishes how many were acked.
+async fn ack_bulk<C: AlarmConfigs>(emitter: &Emitter, cache: &C, ack: &AckRequest) {
+    let names = {
+        let alarms = emitter.runtime.lock().await;
+        bulk_targets(ack, &emitter.definitions, &alarms)
+    };
+
+    let mut acked = 0;
+    for name in names {
+        if ack_alarm(emitter, cache, ack, &name).await == AckOutcome::Acked {
+            acked += 1;
+        }
+    }
+
+    emitter
+        .notify(Notice::AckResult {
+            alarm: ack.alarm.clone(),
+            timestamp: Utc::now(),
+            acked,
+        })
+        .await;
+}
+
+/// Acks the alarm `name`, storing and publishing it only if it was waiting
+/// for an ack.
+async fn ack_alarm<C: AlarmConfigs>(
+    emitter: &Emitter,
+    cache: &C,
+    ack: &AckRequest,
+    name: &str,
+) -> AckOutcome {
+    if !emitter.definitions.contains(name) {
+        emitter
+            .notify(Notice::Error {
+                command: "ack".to_string(),
+                alarm: name.to_string(),
+                timestamp: Utc::now(),
+                message: "unknown alarm".to_string(),
+            })
+            .await;
+        return AckOutcome::Unknown;
+    }
+
+    let (machine, mut event, restored) = {
+        let mut alarms = emitter.runtime.lock().await;
+        let Some(runtime) = alarms.get_mut(name) else {
+            return AckOutcome::NotActive;
+        };
+        let outcome = apply_ack(runtime);
+        if outcome != AckOutcome::Acked {
+            return outcome;
+        }
+        // Alarms restored on start up weren't published yet, only their
+        // stored severity is known
+        let restored = runtime
+            .last
+            .is_none()
+            .then(|| runtime.severity(&emitter.definitions.severities(name)));
+        (runtime.machine, runtime.state_event(name), restored)
+    };
+
+    let request = AckRequest {
+        alarm: name.to_string(),
+        ..ack.clone()
+    };
+    emitter.db.send_ack(&request, machine.state()).await;
+
+    if let Some(severity) = restored {
+        // The discrete alarms are configured in the cache
+        let severity = match severity {
+            Some(severity) => Some(severity),
+            None => cache.get_alm_config(name).await.map(|c| c.severity),
+        };
+        if let Some(severity) = severity {
+            event.alarm.severity = severity;
+        }
+    }
+    event.alarm.ack = AlarmAck::Ack;
+    event.user = request.user;
+    event.comment = request.comment;
+    event.client_timestamp = request.timestamp;
+    emitter.annunciate(event).await;
+    AckOutcome::Acked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::transition::tests::{emitter, event};
+    use crate::alarm::{Alarm, AlarmState, Message, StateMachine};
+    use crate::db::StoredState;
+
+    /// Alarms with no discrete configuration.
+    struct NoConfigs;
+
+    #[async_trait]
+    impl AlarmConfigs for NoConfigs {
+        async fn get_alm_config(&self, _name: &str) -> Option<DigitalAlarm> {
+            None
+        }
+    }
+
+    const CONFIG: &str = r#"
+        sub1:
+          alarm1: {}
+          alarm2: {}
+          alarm3: {}
+          alarm4: {}
+        sub2:
+          alarm1: {}
+    "#;
+
+    /// sub1/alarm1 and sub2/alarm1 waiting for an ack, sub1/alarm2 too with
+    /// a low severity, sub1/alarm3 cleared before the ack and sub1/alarm4
+    /// acked.
+    async fn alarms(emitter: &Emitter) {
+        for name in [
+            "sub1/alarm1",
+            "sub1/alarm2",
+            "sub1/alarm3",
+            "sub1/alarm4",
+            "sub2/alarm1",
+        ] {
+            let mut set = event(name, AlarmState::Set);
+            if name == "sub1/alarm2" {
+                set.alarm.severity = AlarmSeverity::Low;
+            }
+            emitter.publish(set).await;
+        }
+        emitter
+            .publish(event("sub1/alarm3", AlarmState::Reset))
+            .await;
+        let mut alarms = emitter.runtime.lock().await;
+        apply_ack(alarms.get_mut("sub1/alarm4").unwrap());
+    }
+
+    fn runtime(state: IsaState, active: bool, ack: AlarmAck) -> AlarmRuntime {
+        AlarmRuntime {
+            machine: StateMachine::new(state, active),
+            last: Some(Alarm {
+                name: "sub1/alarm1".to_string(),
+                timestamp: Utc::now(),
+                value: 1,
+                state: if active {
+                    AlarmState::Set
+                } else {
+                    AlarmState::Reset
+                },
+                severity: AlarmSeverity::High,
+                ack,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ack_active() {
+        let mut alarm = runtime(IsaState::Unack, true, AlarmAck::NotAck);
+
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::Acked);
+        assert_eq!(alarm.machine.state(), IsaState::Acked);
+        assert_eq!(alarm.last.as_ref().unwrap().ack, AlarmAck::Ack);
+    }
+
+    #[test]
+    fn test_ack_returned_to_normal() {
+        let mut alarm = runtime(IsaState::RtnUnack, false, AlarmAck::NotAck);
+
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::Acked);
+        assert_eq!(alarm.machine.state(), IsaState::Normal);
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::AlreadyAcked);
+    }
+
+    #[test]
+    fn test_ack_twice() {
+        let mut alarm = runtime(IsaState::Unack, true, AlarmAck::NotAck);
+        apply_ack(&mut alarm);
+
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::AlreadyAcked);
+        assert_eq!(alarm.machine.state(), IsaState::Acked);
+    }
+
+    #[test]
+    fn test_ack_never_set() {
+        let mut alarm = AlarmRuntime::default();
+
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::NotActive);
+        assert_eq!(alarm.machine.state(), IsaState::Normal);
+        assert!(alarm.last.is_none());
+    }
+
+    #[test]
+    fn test_ack_not_allowed() {
+        let mut alarm = runtime(IsaState::Shelved, true, AlarmAck::NotAck);
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::NotAllowed);
+        assert_eq!(alarm.machine.state(), IsaState::Shelved);
+        assert_eq!(alarm.last.as_ref().unwrap().ack, AlarmAck::NotAck);
+
+        let mut alarm = runtime(IsaState::OutOfService, false, AlarmAck::NotAck);
+        assert_eq!(apply_ack(&mut alarm), AckOutcome::NotAllowed);
+    }
+
+    #[test]
+    fn test_parse() {
+        let ack = AckRequest::parse("sub1/alarm1").unwrap();
+        assert_eq!(ack.alarm, "sub1/alarm1");
+        assert!(ack.user.is_none());
+
+        let ack = AckRequest::parse(
+            r#"{"alarm": "sub1/alarm1", "user": "jdoe", "comment": "checked on site",
+                "timestamp": "2024-05-01T10:00:00Z"}"#,
+        )
+        .unwrap();
+        assert_eq!(ack.user.as_deref(), Some("jdoe"));
+        assert_eq!(ack.comment.as_deref(), Some("checked on site"));
+        assert_eq!(
+            ack.timestamp.unwrap().to_rfc3339(),
+            "2024-05-01T10:00:00+00:00"
+        );
+
+        let ack = AckRequest::parse(r#"{"alarm": "sub1/alarm1"}"#).unwrap();
+        assert!(ack.comment.is_none());
+
+        assert!(AckRequest::parse(r#"{"user": "jdoe"}"#).is_err());
+    }
+
+    #[test]
+    fn test_bulk() {
+        let ack =
+            AckRequest::parse(r#"{"alarm": "sub1/", "severity": 0, "active": true}"#).unwrap();
+        assert!(ack.is_bulk());
+        assert!(ack.matches("sub1/alarm1"));
+        assert!(!ack.matches("sub10/alarm1"));
+        assert_eq!(ack.severity, Some(AlarmSeverity::Low));
+        assert_eq!(ack.active, Some(true));
+
+        let ack = AckRequest::parse("sub*/alarm2").unwrap();
+        assert!(ack.is_bulk());
+        assert!(ack.matches("sub2/alarm2"));
+        assert!(!ack.matches("sub2/alarm1"));
+
+        assert!(!AckRequest::parse("sub1/alarm1").unwrap().is_bulk());
+    }
+
+    #[tokio::test]
+    async fn test_bulk_targets() {
+        let (emitter, _rx) = emitter(CONFIG);
+        alarms(&emitter).await;
+        let alarms = emitter.runtime.lock().await;
+        let targets = |ack: &AckRequest| bulk_targets(ack, &emitter.definitions, &alarms);
+
+        let mut ack = AckRequest::parse("sub1/").unwrap();
+        assert_eq!(targets(&ack), ["sub1/alarm1", "sub1/alarm2", "sub1/alarm3"]);
+
+        ack.severity = Some(AlarmSeverity::High);
+        assert_eq!(targets(&ack), ["sub1/alarm1", "sub1/alarm3"]);
+
+        ack.active = Some(true);
+        assert_eq!(targets(&ack), ["sub1/alarm1"]);
+        ack.active = Some(false);
+        assert_eq!(targets(&ack), ["sub1/alarm3"]);
+
+        let ack = AckRequest::parse("*/alarm1").unwrap();
+        assert_eq!(targets(&ack), ["sub1/alarm1", "sub2/alarm1"]);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_stale() {
+        let (emitter, _rx) = emitter("sub1:\n  alarm1:\n    stale: {timeout: 10, severity: 0}");
+        emitter
+            .publish(event("sub1/alarm1/stale", AlarmState::Set))
+            .await;
+        let alarms = emitter.runtime.lock().await;
+
+        let ack = AckRequest::parse("sub1/").unwrap();
+        assert_eq!(
+            bulk_targets(&ack, &emitter.definitions, &alarms),
+            ["sub1/alarm1/stale"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bulk_result() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        alarms(&emitter).await;
+        while rx.try_recv().is_ok() {}
+
+        let mut ack = AckRequest::parse("sub1/").unwrap();
+        ack.severity = Some(AlarmSeverity::High);
+        let mut result = || {
+            let mut annunciated = Vec::new();
+            while let Ok(message) = rx.try_recv() {
+                match message {
+                    Message::Alarm(event) => annunciated.push(event.alarm.name),
+                    Message::Notice(Notice::AckResult { acked, .. }) => {
+                        return (annunciated, acked)
+                    }
+                    other => panic!("unexpected {other:?}"),
+                }
+            }
+            panic!("no ack result");
+        };
+
+        ack_bulk(&emitter, &NoConfigs, &ack).await;
+        assert_eq!(
+            result(),
+            (
+                vec!["sub1/alarm1".to_string(), "sub1/alarm3".to_string()],
+                2
+            )
+        );
+
+        // Nothing left to ack
+        ack_bulk(&emitter, &NoConfigs, &ack).await;
+        assert_eq!(result(), (Vec::new(), 0));
+    }
+
+    #[tokio::test]
+    async fn test_ack_restored() {
+        let (emitter, mut rx) = emitter(
+            r#"
+            sub1:
+              alarm1:
+                analog:
+                  hi: {value: 10, severity: 2}
+                  lo: {value: 0, severity: 1}
+                stale: {timeout: 10, severity: 0}
+              alarm2:
+                bits:
+                  - {bit: 0, name: door, severity: 1}
+            "#,
+        );
+        let restored = |name: &str, severity: Option<AlarmSeverity>| StoredState {
+            name: name.to_string(),
+            machine: StateMachine::new(IsaState::Unack, true),
+            shelved_until: None,
+            severity: severity.map(|s| s.to_string()),
+            unack_since: None,
+            escalated: false,
+            value: Some(-1.0),
+        };
+        emitter
+            .runtime
+            .restore_states(vec![
+                restored("sub1/alarm1", Some(AlarmSeverity::Medium)),
+                restored("sub1/alarm1/stale", Some(AlarmSeverity::Low)),
+                restored("sub1/alarm2/door", None),
+            ])
+            .await;
+
+        for (name, severity) in [
+            ("sub1/alarm1", AlarmSeverity::Medium),
+            ("sub1/alarm1/stale", AlarmSeverity::Low),
+            ("sub1/alarm2/door", AlarmSeverity::Medium),
+        ] {
+            let ack = AckRequest::parse(name).unwrap();
+            assert_eq!(
+                ack_alarm(&emitter, &NoConfigs, &ack, name).await,
+                AckOutcome::Acked
+            );
+            match rx.try_recv() {
+                Ok(Message::Alarm(event)) => {
+                    assert_eq!(event.alarm.name, name);
+                    assert_eq!(event.alarm.severity, severity);
+                    assert_eq!(event.alarm.ack, AlarmAck::Ack);
+                }
+                other => panic!("unexpected {other:?}"),
+            }
+        }
+    }
+}
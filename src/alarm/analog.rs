@@ -0,0 +1,204 @@
+use crate::alarm::AlarmSeverity;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Limit of an analog or rate of change alarm that was crossed by a
+/// measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmLimit {
+    HiHi,
+    Hi,
+    Lo,
+    LoLo,
+    Rate,
+}
+
+impl fmt::Display for AlarmLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AlarmLimit::HiHi => "hihi",
+            AlarmLimit::Hi => "hi",
+            AlarmLimit::Lo => "lo",
+            AlarmLimit::LoLo => "lolo",
+            AlarmLimit::Rate => "rate",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Limit {
+    pub value: f64,
+    pub severity: AlarmSeverity,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeadbandMode {
+    #[default]
+    Absolute,
+    Percent,
+}
+
+/// Hysteresis applied to an active limit. The alarm only leaves the limit
+/// once the value moved back past the limit minus the deadband.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Deadband {
+    pub value: f64,
+
+    #[serde(default)]
+    pub mode: DeadbandMode,
+}
+
+impl Deadband {
+    /// Width of the deadband around the `limit` value.
+    pub fn width(&self, limit: f64) -> f64 {
+        match self.mode {
+            DeadbandMode::Absolute => self.value.abs(),
+            DeadbandMode::Percent => (limit * self.value / 100.0).abs(),
+        }
+    }
+}
+
+/// Up to four limits evaluated against a floating point measurement.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AnalogLimits {
+    #[serde(default)]
+    pub hihi: Option<Limit>,
+    #[serde(default)]
+    pub hi: Option<Limit>,
+    #[serde(default)]
+    pub lo: Option<Limit>,
+    #[serde(default)]
+    pub lolo: Option<Limit>,
+}
+
+impl AnalogLimits {
+    /// Returns the most severe limit crossed by `value`, if any.
+    ///
+    /// `active` is the limit the alarm is currently set at. The `deadband` is
+    /// only applied to this limit and the less severe ones on the same side.
+    pub fn evaluate(
+        &self,
+        value: f64,
+        active: Option<AlarmLimit>,
+        deadband: Option<&Deadband>,
+    ) -> Option<AlarmLimit> {
+        [AlarmLimit::HiHi, AlarmLimit::Hi, AlarmLimit::LoLo, AlarmLimit::Lo]
+            .into_iter()
+            .find(|limit| {
+                let Some(l) = self.get(*limit) else {
+                    return false;
+                };
+                let band = match deadband {
+                    Some(d) if Self::holds(active, *limit) => d.width(l.value),
+                    _ => 0.0,
+                };
+                if Self::is_high(*limit) {
+                    value >= l.value - band
+                } else {
+                    value <= l.value + band
+                }
+            })
+    }
+
+    pub fn get(&self, limit: AlarmLimit) -> Option<&Limit> {
+        match limit {
+            AlarmLimit::HiHi => self.hihi.as_ref(),
+            AlarmLimit::Hi => self.hi.as_ref(),
+            AlarmLimit::Lo => self.lo.as_ref(),
+            AlarmLimit::LoLo => self.lolo.as_ref(),
+            AlarmLimit::Rate => None,
+        }
+    }
+
+    fn is_high(limit: AlarmLimit) -> bool {
+        matches!(limit, AlarmLimit::HiHi | AlarmLimit::Hi)
+    }
+
+    /// An active limit holds itself and the less severe limit on the same
+    /// side, so a HIHI alarm leaving its deadband goes back to HI.
+    fn holds(active: Option<AlarmLimit>, limit: AlarmLimit) -> bool {
+        match active {
+            Some(a) if a == limit => true,
+            Some(AlarmLimit::HiHi) => limit == AlarmLimit::Hi,
+            Some(AlarmLimit::LoLo) => limit == AlarmLimit::Lo,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> AnalogLimits {
+        AnalogLimits {
+            hihi: Some(Limit { value: 120.0, severity: AlarmSeverity::High }),
+            hi: Some(Limit { value: 100.0, severity: AlarmSeverity::Low }),
+            lo: Some(Limit { value: 10.0, severity: AlarmSeverity::Low }),
+            lolo: Some(Limit { value: 0.0, severity: AlarmSeverity::High }),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let limits = limits();
+
+        assert_eq!(limits.evaluate(50.0, None, None), None);
+        assert_eq!(limits.evaluate(100.0, None, None), Some(AlarmLimit::Hi));
+        assert_eq!(limits.evaluate(130.5, None, None), Some(AlarmLimit::HiHi));
+        assert_eq!(limits.evaluate(5.0, None, None), Some(AlarmLimit::Lo));
+        assert_eq!(limits.evaluate(-3.2, None, None), Some(AlarmLimit::LoLo));
+    }
+
+    #[test]
+    fn test_missing_limits() {
+        let limits = AnalogLimits {
+            hi: Some(Limit { value: 100.0, severity: AlarmSeverity::Low }),
+            ..Default::default()
+        };
+
+        assert_eq!(limits.evaluate(500.0, None, None), Some(AlarmLimit::Hi));
+        assert_eq!(limits.evaluate(-500.0, None, None), None);
+    }
+
+    #[test]
+    fn test_absolute_deadband() {
+        let limits = limits();
+        let deadband = Deadband { value: 5.0, mode: DeadbandMode::Absolute };
+        let active = Some(AlarmLimit::Hi);
+
+        assert_eq!(limits.evaluate(97.0, active, Some(&deadband)), active);
+        assert_eq!(limits.evaluate(95.0, active, Some(&deadband)), active);
+        assert_eq!(limits.evaluate(94.9, active, Some(&deadband)), None);
+
+        // The deadband doesn't delay the alarm from being set
+        assert_eq!(limits.evaluate(97.0, None, Some(&deadband)), None);
+    }
+
+    #[test]
+    fn test_percent_deadband() {
+        let limits = limits();
+        let deadband = Deadband { value: 10.0, mode: DeadbandMode::Percent };
+        let active = Some(AlarmLimit::Lo);
+
+        assert_eq!(limits.evaluate(19.0, active, Some(&deadband)), None);
+        assert_eq!(limits.evaluate(10.5, active, Some(&deadband)), active);
+        assert_eq!(limits.evaluate(11.0, active, Some(&deadband)), active);
+        assert_eq!(limits.evaluate(11.1, active, Some(&deadband)), None);
+    }
+
+    #[test]
+    fn test_deadband_steps_down() {
+        let limits = limits();
+        let deadband = Deadband { value: 5.0, mode: DeadbandMode::Absolute };
+        let active = Some(AlarmLimit::HiHi);
+
+        assert_eq!(limits.evaluate(116.0, active, Some(&deadband)), active);
+        assert_eq!(limits.evaluate(110.0, active, Some(&deadband)), Some(AlarmLimit::Hi));
+        assert_eq!(limits.evaluate(96.0, active, Some(&deadband)), Some(AlarmLimit::Hi));
+        assert_eq!(limits.evaluate(90.0, active, Some(&deadband)), None);
+    }
+}
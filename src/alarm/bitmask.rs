@@ -0,0 +1,74 @@
+use crate::alarm::AlarmSeverity;
+use serde::Deserialize;
+
+/// Alarm mapped to a single bit of a packed status measurement.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BitAlarm {
+    /// Bit position, 0 being the least significant bit.
+    pub bit: u8,
+
+    /// Name of the alarm, appended to the name of the packed alarm.
+    pub name: String,
+
+    pub severity: AlarmSeverity,
+
+    /// Value of the bit when the alarm is not active, 0 or 1.
+    #[serde(default)]
+    pub normal: u8,
+}
+
+impl BitAlarm {
+    pub fn is_active(&self, value: i64) -> bool {
+        ((value >> self.bit) & 1) as u8 != self.normal
+    }
+
+    pub fn alarm_name(&self, parent: &str) -> String {
+        format!("{parent}/{}", self.name)
+    }
+
+    pub fn validate(&self, parent: &str) -> Result<(), String> {
+        if self.bit >= 64 {
+            return Err(format!("bit {} of '{parent}' is out of range", self.bit));
+        }
+        if self.normal > 1 {
+            return Err(format!(
+                "normal value of bit {} of '{parent}' must be 0 or 1",
+                self.bit
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bit(bit: u8, normal: u8) -> BitAlarm {
+        BitAlarm {
+            bit,
+            name: "bit".to_string(),
+            severity: AlarmSeverity::Low,
+            normal,
+        }
+    }
+
+    #[test]
+    fn test_is_active() {
+        assert!(bit(0, 0).is_active(0b0001));
+        assert!(!bit(0, 0).is_active(0b0010));
+        assert!(bit(3, 0).is_active(0b1000));
+        assert!(bit(31, 0).is_active(1 << 31));
+
+        // Normally set bit
+        assert!(!bit(2, 1).is_active(0b0100));
+        assert!(bit(2, 1).is_active(0b1011));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(bit(63, 1).validate("a/b").is_ok());
+        assert!(bit(64, 0).validate("a/b").is_err());
+        assert!(bit(1, 2).validate("a/b").is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::alarm::{Emitter, Notice};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What to do with an alarm found chattering.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatterAction {
+    /// Only report it.
+    #[default]
+    None,
+    /// Shelve the alarm for `duration` seconds.
+    Shelve,
+    /// Delay the activations by `duration` seconds until it settles.
+    Delay,
+}
+
+/// Flags the alarm as chattering when it's set more than `toggles` times in
+/// `window` seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chattering {
+    pub toggles: usize,
+    pub window: f64,
+    #[serde(default)]
+    pub action: ChatterAction,
+    /// Seconds the alarm is shelved or delayed for.
+    #[serde(default)]
+    pub duration: f64,
+}
+
+impl Chattering {
+    pub fn window(&self) -> Duration {
+        Duration::from_secs_f64(self.window)
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration.max(0.0))
+    }
+
+    /// Rejects the windows and durations that aren't a number of seconds
+    /// the server can count.
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        let window = Duration::try_from_secs_f64(self.window).ok();
+        if window.filter(|w| !w.is_zero()).is_none() {
+            return Err(format!("invalid chattering window {} of '{name}'", self.window));
+        }
+
+        let until = Duration::try_from_secs_f64(self.duration)
+            .ok()
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .and_then(|d| Utc::now().checked_add_signed(d));
+        if until.is_none() {
+            return Err(format!("invalid chattering duration {} of '{name}'", self.duration));
+        }
+        Ok(())
+    }
+}
+
+/// Activations of an alarm, kept to detect chattering.
+#[derive(Debug, Default)]
+pub struct ChatterState {
+    toggles: VecDeque<Instant>,
+    pub chattering: bool,
+    /// Activations since the server started.
+    pub activations: u64,
+    /// Times the alarm was found chattering since the server started.
+    pub episodes: u64,
+}
+
+impl ChatterState {
+    /// Records an activation. Returns whether the alarm just started
+    /// chattering.
+    pub fn push(&mut self, config: &Chattering, now: Instant) -> bool {
+        self.activations += 1;
+        self.toggles.push_back(now);
+        self.expire(config, now);
+
+        if self.chattering || self.toggles.len() <= config.toggles {
+            return false;
+        }
+        self.chattering = true;
+        self.episodes += 1;
+        true
+    }
+
+    /// Returns whether a chattering alarm just settled.
+    pub fn settled(&mut self, config: &Chattering, now: Instant) -> bool {
+        self.expire(config, now);
+        if !self.chattering || self.toggles.len() > config.toggles {
+            return false;
+        }
+        self.chattering = false;
+        true
+    }
+
+    pub fn toggles(&self) -> usize {
+        self.toggles.len()
+    }
+
+    fn expire(&mut self, config: &Chattering, now: Instant) {
+        while let Some(first) = self.toggles.front() {
+            if now.duration_since(*first) <= config.window() {
+                break;
+            }
+            self.toggles.pop_front();
+        }
+    }
+}
+
+/// Chattering counts of an alarm, for bad actor reports.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatterCount {
+    pub alarm: String,
+    pub activations: u64,
+    pub episodes: u64,
+    pub chattering: bool,
+}
+
+/// Reports the chattering alarms that settled and answers the report
+/// requests.
+#[derive(Debug, Clone)]
+pub struct ChatterMonitor {
+    emitter: Emitter,
+}
+
+impl ChatterMonitor {
+    pub fn new(emitter: Emitter) -> Self {
+        Self { emitter }
+    }
+
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            let now = Instant::now();
+            let mut settled = Vec::new();
+            {
+                let mut alarms = self.emitter.runtime.lock().await;
+                for definition in self.emitter.definitions.iter() {
+                    let Some(config) = &definition.chattering else {
+                        continue;
+                    };
+                    let Some(runtime) = alarms.get_mut(&definition.name) else {
+                        continue;
+                    };
+                    if runtime.chatter.settled(config, now) {
+                        settled.push(definition.name.clone());
+                    }
+                }
+            }
+
+            for alarm in settled {
+                self.emitter
+                    .notify(Notice::ChatteringEnd {
+                        alarm,
+                        timestamp: Utc::now(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Publishes the counts of the alarms that were ever set, the worst
+    /// first.
+    pub async fn report(&self) {
+        let mut counts: Vec<ChatterCount> = self
+            .emitter
+            .runtime
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, runtime)| runtime.chatter.activations > 0)
+            .map(|(name, runtime)| ChatterCount {
+                alarm: name.clone(),
+                activations: runtime.chatter.activations,
+                episodes: runtime.chatter.episodes,
+                chattering: runtime.chatter.chattering,
+            })
+            .collect();
+        counts.sort_by(|a, b| {
+            (b.episodes, b.activations)
+                .cmp(&(a.episodes, a.activations))
+                .then_with(|| a.alarm.cmp(&b.alarm))
+        });
+
+        self.emitter
+            .notify(Notice::ChatteringReport {
+                timestamp: Utc::now(),
+                alarms: counts,
+            })
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chattering() {
+        let config = Chattering {
+            toggles: 2,
+            window: 10.0,
+            action: ChatterAction::None,
+            duration: 0.0,
+        };
+        let mut state = ChatterState::default();
+        let start = Instant::now();
+
+        assert!(!state.push(&config, start));
+        assert!(!state.push(&config, start + Duration::from_secs(1)));
+        assert!(state.push(&config, start + Duration::from_secs(2)));
+        assert!(!state.push(&config, start + Duration::from_secs(3)));
+        assert_eq!(state.episodes, 1);
+        assert_eq!(state.activations, 4);
+
+        assert!(!state.settled(&config, start + Duration::from_secs(5)));
+        assert!(state.settled(&config, start + Duration::from_secs(13)));
+        assert_eq!(state.toggles(), 1);
+        assert!(!state.chattering);
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = |window: f64, duration: f64| Chattering {
+            toggles: 2,
+            window,
+            action: ChatterAction::Shelve,
+            duration,
+        };
+
+        assert!(config(10.0, 600.0).validate("sub1/alarm1").is_ok());
+        assert!(config(0.5, 0.0).validate("sub1/alarm1").is_ok());
+        for window in [0.0, -10.0, f64::NAN, f64::INFINITY, 1e300] {
+            assert!(config(window, 600.0).validate("sub1/alarm1").is_err());
+        }
+        for duration in [-1.0, f64::NAN, 1e300] {
+            assert!(config(10.0, duration).validate("sub1/alarm1").is_err());
+        }
+    }
+}
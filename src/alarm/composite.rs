@@ -0,0 +1,99 @@
+use crate::alarm::AlarmSeverity;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompositeMode {
+    Any,
+    All,
+    AtLeast,
+}
+
+/// Alarm derived from the state of other alarms, e.g. an area summary.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Composite {
+    /// Full names of the member alarms.
+    pub members: Vec<String>,
+
+    pub mode: CompositeMode,
+
+    /// Number of active members needed by the `at_least` mode.
+    #[serde(default)]
+    pub count: Option<usize>,
+
+    pub severity: AlarmSeverity,
+}
+
+impl Composite {
+    /// Whether the composite is active with `active` of its members active.
+    pub fn is_active(&self, active: usize) -> bool {
+        match self.mode {
+            CompositeMode::Any => active > 0,
+            CompositeMode::All => active == self.members.len(),
+            CompositeMode::AtLeast => active >= self.count.unwrap_or(1),
+        }
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if self.members.is_empty() {
+            return Err(format!("composite '{name}' has no members"));
+        }
+        if self.members.iter().any(|m| m == name) {
+            return Err(format!("composite '{name}' is a member of itself"));
+        }
+        if self.mode == CompositeMode::AtLeast {
+            let Some(count) = self.count else {
+                return Err(format!(
+                    "composite '{name}' needs a count for the at_least mode"
+                ));
+            };
+            // A count of zero would always be active, more than the members
+            // never
+            if count == 0 || count > self.members.len() {
+                return Err(format!("invalid count {count} of composite '{name}'"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn composite(mode: CompositeMode, count: Option<usize>) -> Composite {
+        Composite {
+            members: vec!["a/1".to_string(), "a/2".to_string(), "a/3".to_string()],
+            mode,
+            count,
+            severity: AlarmSeverity::Low,
+        }
+    }
+
+    #[test]
+    fn test_modes() {
+        let any = composite(CompositeMode::Any, None);
+        assert!(!any.is_active(0));
+        assert!(any.is_active(1));
+
+        let all = composite(CompositeMode::All, None);
+        assert!(!all.is_active(2));
+        assert!(all.is_active(3));
+
+        let at_least = composite(CompositeMode::AtLeast, Some(2));
+        assert!(!at_least.is_active(1));
+        assert!(at_least.is_active(2));
+        assert!(at_least.is_active(3));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(composite(CompositeMode::Any, None).validate("a/summary").is_ok());
+        assert!(composite(CompositeMode::Any, None).validate("a/1").is_err());
+        assert!(composite(CompositeMode::AtLeast, None).validate("a/summary").is_err());
+        for (count, valid) in [(0, false), (1, true), (3, true), (4, false)] {
+            let at_least = composite(CompositeMode::AtLeast, Some(count));
+            assert_eq!(at_least.validate("a/summary").is_ok(), valid, "{count}");
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use crate::alarm::analog::{AlarmLimit, AnalogLimits, Deadband};
+use crate::alarm::bitmask::BitAlarm;
+use crate::alarm::chattering::Chattering;
+use crate::alarm::composite::Composite;
+use crate::alarm::expression::ExpressionAlarm;
+use crate::alarm::rate::RateOfChange;
+use crate::alarm::runtime::AlarmRuntime;
+use crate::alarm::suppression::Suppression;
+use crate::alarm::watchdog::Stale;
+use crate::alarm::AlarmSeverity;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Options of a single alarm read from the alarm configuration file.
+///
+/// The discrete `set`/`reset` configuration is still served by the `Cache`,
+/// this holds everything the server needs on top of it.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AlarmDefinition {
+    #[serde(skip)]
+    pub name: String,
+
+    #[serde(default)]
+    pub analog: Option<AnalogLimits>,
+
+    #[serde(default)]
+    pub rate: Option<RateOfChange>,
+
+    /// Condition given as an expression, parsed when the file is loaded.
+    #[serde(default)]
+    pub expression: Option<ExpressionAlarm>,
+
+    /// Separate alarms for the bits of a packed status measurement. Each bit
+    /// alarm is named `<alarm>/<bit name>`.
+    #[serde(default)]
+    pub bits: Option<Vec<BitAlarm>>,
+
+    /// Alarm derived from the state of other alarms.
+    #[serde(default)]
+    pub composite: Option<Composite>,
+
+    /// Hysteresis applied before an analog limit is cleared.
+    #[serde(default)]
+    pub deadband: Option<Deadband>,
+
+    /// Seconds the condition must hold before the alarm is set.
+    #[serde(default)]
+    pub on_delay: Option<f64>,
+
+    /// Seconds the condition must be gone before the alarm is reset.
+    #[serde(default)]
+    pub off_delay: Option<f64>,
+
+    /// Raises a stale alarm when the triggers stop arriving.
+    #[serde(default)]
+    pub stale: Option<Stale>,
+
+    /// Suppressed by design, the alarm is evaluated and stored but never
+    /// annunciated.
+    #[serde(default)]
+    pub suppressed: bool,
+
+    /// Keeps the alarm set once the condition clears until an operator acks
+    /// and resets it.
+    #[serde(default)]
+    pub latching: bool,
+
+    /// Detects the alarm toggling too often.
+    #[serde(default)]
+    pub chattering: Option<Chattering>,
+}
+
+impl AlarmDefinition {
+    pub fn on_delay(&self) -> Option<Duration> {
+        Self::delay(self.on_delay)
+    }
+
+    pub fn off_delay(&self) -> Option<Duration> {
+        Self::delay(self.off_delay)
+    }
+
+    fn delay(secs: Option<f64>) -> Option<Duration> {
+        secs.filter(|s| *s > 0.0).map(Duration::from_secs_f64)
+    }
+
+    /// Checks the on/off delays fit in a `Duration`, zero or negative ones
+    /// being no delay.
+    fn validate(&self) -> Result<(), String> {
+        for (option, secs) in [("on_delay", self.on_delay), ("off_delay", self.off_delay)] {
+            let Some(secs) = secs else {
+                continue;
+            };
+            if !secs.is_finite() || Duration::try_from_secs_f64(secs.max(0.0)).is_err() {
+                return Err(format!("invalid {option} {secs} of '{}'", self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// All the alarms from the configuration file, indexed by their full name
+/// (`<area>/<alarm>`).
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    alarms: Arc<HashMap<String, AlarmDefinition>>,
+    /// Composite alarms each alarm is a member of.
+    composites: Arc<HashMap<String, Vec<String>>>,
+    suppression: Suppression,
+}
+
+impl Definitions {
+    pub fn load(path: &str) -> Self {
+        let source = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("alarm config file not found. Path: '{path}'"));
+
+        Self::from_yaml(&source).expect("Invalid alarm configuration file")
+    }
+
+    pub fn from_yaml(source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let areas: HashMap<String, HashMap<String, AlarmDefinition>> =
+            serde_yaml::from_str(source)?;
+
+        let mut alarms = HashMap::new();
+        for (area, area_alarms) in areas {
+            for (alarm, mut definition) in area_alarms {
+                definition.name = format!("{area}/{alarm}");
+                definition.validate()?;
+                if let Some(rate) = &definition.rate {
+                    rate.validate(&definition.name)?;
+                }
+                if let Some(stale) = &definition.stale {
+                    stale.validate(&definition.name)?;
+                }
+                if let Some(chattering) = &definition.chattering {
+                    chattering.validate(&definition.name)?;
+                }
+                alarms.insert(definition.name.clone(), definition);
+            }
+        }
+
+        let parents: Vec<AlarmDefinition> = alarms
+            .values()
+            .filter(|d| d.bits.is_some())
+            .cloned()
+            .collect();
+        for parent in parents {
+            for bit in parent.bits.iter().flatten() {
+                bit.validate(&parent.name)?;
+                // The bit alarms share the delays of the packed alarm
+                let child = AlarmDefinition {
+                    name: bit.alarm_name(&parent.name),
+                    on_delay: parent.on_delay,
+                    off_delay: parent.off_delay,
+                    suppressed: parent.suppressed,
+                    ..Default::default()
+                };
+                if alarms.contains_key(&child.name) {
+                    return Err(format!("alarm '{}' is defined twice", child.name).into());
+                }
+                alarms.insert(child.name.clone(), child);
+            }
+        }
+
+        let mut composites: HashMap<String, Vec<String>> = HashMap::new();
+        for definition in alarms.values() {
+            let Some(composite) = &definition.composite else {
+                continue;
+            };
+            composite.validate(&definition.name)?;
+            // A composite follows its members right away
+            if definition.on_delay.is_some()
+                || definition.off_delay.is_some()
+                || definition.latching
+            {
+                return Err(format!(
+                    "composite '{}' can't have delays or be latching",
+                    definition.name
+                )
+                .into());
+            }
+            for member in &composite.members {
+                if !alarms.contains_key(member) {
+                    return Err(format!(
+                        "unknown member '{member}' of composite '{}'",
+                        definition.name
+                    )
+                    .into());
+                }
+                composites
+                    .entry(member.clone())
+                    .or_default()
+                    .push(definition.name.clone());
+            }
+        }
+        check_cycles(&alarms)?;
+
+        Ok(Self {
+            alarms: Arc::new(alarms),
+            composites: Arc::new(composites),
+            suppression: Suppression::default(),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&AlarmDefinition> {
+        self.alarms.get(name)
+    }
+
+    /// Whether `name` is a configured alarm, including the stale alarms of
+    /// the watchdog.
+    pub fn contains(&self, name: &str) -> bool {
+        self.alarms.contains_key(name)
+            || name
+                .strip_suffix("/stale")
+                .and_then(|parent| self.get(parent))
+                .is_some_and(|d| d.stale.is_some())
+    }
+
+    /// Severities configured for `name`, including the bit alarms and the
+    /// stale alarms configured on their parent. Empty for the discrete
+    /// alarms, configured in the `Cache`.
+    pub fn severities(&self, name: &str) -> Vec<AlarmSeverity> {
+        let mut severities = Vec::new();
+        if let Some(definition) = self.get(name) {
+            if let Some(analog) = &definition.analog {
+                severities.extend(
+                    [
+                        AlarmLimit::HiHi,
+                        AlarmLimit::Hi,
+                        AlarmLimit::Lo,
+                        AlarmLimit::LoLo,
+                    ]
+                    .into_iter()
+                    .filter_map(|limit| analog.get(limit))
+                    .map(|limit| limit.severity.clone()),
+                );
+            }
+            severities.extend(definition.rate.as_ref().map(|r| r.severity.clone()));
+            severities.extend(definition.expression.as_ref().map(|e| e.severity.clone()));
+            severities.extend(definition.composite.as_ref().map(|c| c.severity.clone()));
+        }
+
+        if let Some((parent, child)) = name.rsplit_once('/') {
+            if let Some(parent) = self.get(parent) {
+                severities.extend(
+                    parent
+                        .bits
+                        .iter()
+                        .flatten()
+                        .filter(|bit| bit.name == child)
+                        .map(|bit| bit.severity.clone()),
+                );
+                if child == "stale" {
+                    severities.extend(parent.stale.as_ref().map(|s| s.severity.clone()));
+                }
+            }
+        }
+        severities
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AlarmDefinition> {
+        self.alarms.values()
+    }
+
+    /// Names of all the alarms, including the stale alarms of the watchdog.
+    pub fn names(&self) -> impl Iterator<Item = String> + '_ {
+        self.iter().flat_map(|d| {
+            let stale = d.stale.as_ref().map(|_| Stale::alarm_name(&d.name));
+            std::iter::once(d.name.clone()).chain(stale)
+        })
+    }
+
+    pub fn set_suppression(&mut self, suppression: Suppression) {
+        self.suppression = suppression;
+    }
+
+    pub fn suppression(&self) -> &Suppression {
+        &self.suppression
+    }
+
+    /// Whether `name` is suppressed by design or by one of the rules.
+    pub fn is_suppressed(&self, name: &str, alarms: &HashMap<String, AlarmRuntime>) -> bool {
+        self.get(name).is_some_and(|d| d.suppressed)
+            || self.suppression.is_suppressed(name, alarms)
+    }
+
+    /// Composite alarms having `member` as one of their members.
+    pub fn composites_of(&self, member: &str) -> impl Iterator<Item = &AlarmDefinition> {
+        self.composites
+            .get(member)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| self.alarms.get(name))
+    }
+}
+
+/// Rejects composites that are members of themselves through other
+/// composites, their state would never settle.
+fn check_cycles(alarms: &HashMap<String, AlarmDefinition>) -> Result<(), String> {
+    fn visit<'a>(
+        name: &'a str,
+        alarms: &'a HashMap<String, AlarmDefinition>,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+    ) -> Result<(), String> {
+        if done.contains(name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|n| *n == name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(name);
+            return Err(format!("composite cycle {}", cycle.join(" -> ")));
+        }
+        let Some(composite) = alarms.get(name).and_then(|d| d.composite.as_ref()) else {
+            return Ok(());
+        };
+
+        path.push(name);
+        for member in &composite.members {
+            visit(member, alarms, path, done)?;
+        }
+        path.pop();
+        done.insert(name);
+        Ok(())
+    }
+
+    let mut names: Vec<&String> = alarms.keys().collect();
+    names.sort();
+    let mut done = HashSet::new();
+    for name in names {
+        visit(name, alarms, &mut Vec::new(), &mut done)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::AlarmLimit;
+
+    #[test]
+    fn test_example() {
+        let definitions = Definitions::load("examples/config.yaml");
+
+        let alarm = definitions.get("sub1/alarm1").unwrap();
+        assert_eq!(alarm.name, "sub1/alarm1");
+        assert!(alarm.analog.is_none());
+        assert_eq!(alarm.on_delay(), Some(Duration::from_secs(5)));
+        assert_eq!(alarm.off_delay(), None);
+        assert!(!alarm.latching);
+        assert!(definitions.get("sub1/alarm2").unwrap().latching);
+
+        let chattering = definitions.get("sub2/alarm1").unwrap().chattering.as_ref().unwrap();
+        assert_eq!(chattering.toggles, 5);
+        assert_eq!(chattering.action, crate::alarm::ChatterAction::Shelve);
+        assert_eq!(chattering.duration(), Duration::from_secs(600));
+
+        let analog = definitions.get("sub3/temperature").unwrap();
+        let limits = analog.analog.as_ref().unwrap();
+        assert_eq!(limits.evaluate(130.0, None, None), Some(AlarmLimit::HiHi));
+        assert!(limits.lo.is_none());
+        assert_eq!(analog.deadband.as_ref().unwrap().width(100.0), 2.0);
+
+        let rate = definitions.get("sub3/pressure").unwrap();
+        assert!(rate.analog.is_none());
+        assert_eq!(rate.rate.as_ref().unwrap().limit, 5.0);
+        assert_eq!(rate.stale.as_ref().unwrap().timeout(), Duration::from_secs(30));
+
+        let expression = definitions.get("sub3/status").unwrap();
+        let condition = &expression.expression.as_ref().unwrap().condition;
+        assert!(condition.is_true(6.0));
+        assert!(!condition.is_true(2.0));
+    }
+
+    #[test]
+    fn test_invalid_expression() {
+        let config = r#"
+            sub1:
+              alarm1:
+                expression:
+                  condition: "value >> > 2"
+                  severity: 1
+        "#;
+
+        assert!(Definitions::from_yaml(config).is_err());
+
+        let config = r#"
+            sub1:
+              alarm1:
+                chattering:
+                  toggles: 5
+                  window: -60
+        "#;
+        assert!(Definitions::from_yaml(config).is_err());
+    }
+
+    #[test]
+    fn test_invalid_delays() {
+        let config = |option: &str, secs: &str| format!("sub1:\n  alarm1:\n    {option}: {secs}");
+
+        for option in ["on_delay", "off_delay"] {
+            for secs in [".inf", "-.inf", ".nan", "1e300"] {
+                assert!(Definitions::from_yaml(&config(option, secs)).is_err());
+            }
+            for secs in ["0", "-1", "2.5"] {
+                assert!(Definitions::from_yaml(&config(option, secs)).is_ok());
+            }
+        }
+        let definitions = Definitions::from_yaml(&config("off_delay", "-1")).unwrap();
+        assert_eq!(definitions.get("sub1/alarm1").unwrap().off_delay(), None);
+    }
+
+    #[test]
+    fn test_bits() {
+        let definitions = Definitions::load("examples/config.yaml");
+
+        let packed = definitions.get("sub3/plc").unwrap();
+        assert_eq!(packed.bits.as_ref().unwrap().len(), 2);
+
+        let bit = definitions.get("sub3/plc/door_open").unwrap();
+        assert!(bit.bits.is_none());
+        assert_eq!(bit.off_delay(), Some(Duration::from_secs(2)));
+        assert!(definitions.get("sub3/plc/no_power").is_some());
+        assert!(definitions.contains("sub3/plc/no_power"));
+        assert!(definitions.contains("sub3/pressure/stale"));
+        assert!(!definitions.contains("sub3/plc/stale"));
+        assert!(!definitions.contains("sub3/missing"));
+    }
+
+    #[test]
+    fn test_composite() {
+        let definitions = Definitions::load("examples/config.yaml");
+
+        let composites: Vec<_> = definitions
+            .composites_of("sub1/alarm2")
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(composites, vec!["sub1/summary"]);
+        assert_eq!(definitions.composites_of("sub2/alarm1").count(), 0);
+
+        let config = r#"
+            sub1:
+              summary:
+                composite:
+                  members: [sub1/missing]
+                  mode: any
+                  severity: 1
+        "#;
+        assert!(Definitions::from_yaml(config).is_err());
+
+        // The state of a composite follows its members right away
+        for option in ["on_delay: 5", "off_delay: 5", "latching: true"] {
+            let config = format!(
+                "sub1:\n  alarm1: {{}}\n  summary:\n    {option}\n    composite:\n      \
+                 members: [sub1/alarm1]\n      mode: any\n      severity: 1"
+            );
+            assert!(Definitions::from_yaml(&config).is_err(), "{option}");
+        }
+    }
+
+    #[test]
+    fn test_composite_cycle() {
+        let config = r#"
+            sub1:
+              alarm1: {}
+              summary:
+                composite:
+                  members: [sub1/alarm1, sub2/summary]
+                  mode: any
+                  severity: 1
+            sub2:
+              summary:
+                composite:
+                  members: [sub3/summary]
+                  mode: any
+                  severity: 1
+            sub3:
+              summary:
+                composite:
+                  members: [sub1/summary]
+                  mode: all
+                  severity: 1
+        "#;
+        let error = Definitions::from_yaml(config).unwrap_err().to_string();
+        assert!(error.contains("cycle"), "{error}");
+
+        // Composites sharing members aren't a cycle
+        let config = r#"
+            sub1:
+              alarm1: {}
+              summary:
+                composite:
+                  members: [sub1/alarm1]
+                  mode: any
+                  severity: 1
+              plant:
+                composite:
+                  members: [sub1/summary, sub1/alarm1]
+                  mode: any
+                  severity: 1
+        "#;
+        assert!(Definitions::from_yaml(config).is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::alarm::Emitter;
+use crate::config::EscalationPolicy;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Escalates the alarms left unacknowledged longer than the policy of their
+/// severity allows.
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    emitter: Emitter,
+    policies: Arc<Vec<EscalationPolicy>>,
+}
+
+impl Escalation {
+    pub fn new(emitter: Emitter, policies: Vec<EscalationPolicy>) -> Self {
+        Self {
+            emitter,
+            policies: Arc::new(policies),
+        }
+    }
+
+    pub async fn run(&self) {
+        if self.policies.is_empty() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            self.check().await;
+        }
+    }
+
+    async fn check(&self) {
+        let now = Utc::now();
+        let mut events = Vec::new();
+
+        {
+            let mut alarms = self.emitter.runtime.lock().await;
+            for (name, runtime) in alarms.iter_mut() {
+                // Also catches the acks, which don't go through the emitter
+                runtime.track_unack(now);
+                if runtime.escalated {
+                    continue;
+                }
+                let Some(since) = runtime.unack_since else {
+                    continue;
+                };
+
+                let Some(policy) = self
+                    .policies
+                    .iter()
+                    .find(|p| runtime.severity_is(&p.severity))
+                else {
+                    continue;
+                };
+                if (now - since).to_std().unwrap_or_default() < policy.after() {
+                    continue;
+                }
+
+                runtime.escalated = true;
+                let mut event = runtime.state_event(name);
+                event.escalated = true;
+                event.escalated_to = Some(
+                    policy
+                        .raise_to
+                        .clone()
+                        .unwrap_or_else(|| policy.severity.clone()),
+                );
+                events.push(event);
+            }
+        }
+
+        for event in events {
+            self.emitter.escalate(event).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::ack::apply_ack;
+    use crate::alarm::transition::tests::{emitter, event};
+    use crate::alarm::{AlarmSeverity, AlarmState, IsaState, Message, StateMachine};
+    use crate::db::StoredState;
+
+    fn escalation(emitter: &Emitter) -> Escalation {
+        Escalation::new(
+            emitter.clone(),
+            vec![EscalationPolicy {
+                severity: AlarmSeverity::Low,
+                after: 300,
+                raise_to: Some(AlarmSeverity::High),
+            }],
+        )
+    }
+
+    async fn unack_for(emitter: &Emitter, name: &str, seconds: i64) {
+        let mut alarms = emitter.runtime.lock().await;
+        let runtime = alarms.get_mut(name).unwrap();
+        runtime.unack_since = Some(Utc::now() - chrono::Duration::seconds(seconds));
+    }
+
+    #[tokio::test]
+    async fn test_escalate() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}\n  alarm2: {}");
+        let escalation = escalation(&emitter);
+        for name in ["sub1/alarm1", "sub1/alarm2"] {
+            let mut set = event(name, AlarmState::Set);
+            set.alarm.severity = AlarmSeverity::Low;
+            emitter.publish(set).await;
+            assert!(matches!(rx.try_recv(), Ok(Message::Alarm(_))));
+        }
+
+        // The time starts with the activation, not with the first check
+        let since = emitter.runtime.lock().await["sub1/alarm1"].unack_since;
+        assert!(since.is_some());
+        escalation.check().await;
+        assert!(rx.try_recv().is_err());
+        assert_eq!(emitter.runtime.lock().await["sub1/alarm1"].unack_since, since);
+
+        unack_for(&emitter, "sub1/alarm1", 301).await;
+        unack_for(&emitter, "sub1/alarm2", 299).await;
+        escalation.check().await;
+        let Ok(Message::Escalated(escalated)) = rx.try_recv() else {
+            panic!("alarm not escalated");
+        };
+        assert_eq!(escalated.alarm.name, "sub1/alarm1");
+        assert_eq!(escalated.alarm.severity, AlarmSeverity::High);
+        assert!(escalated.escalated);
+        assert!(rx.try_recv().is_err());
+
+        // Escalated once, and the severity of the alarm itself is kept
+        escalation.check().await;
+        assert!(rx.try_recv().is_err());
+        {
+            let alarms = emitter.runtime.lock().await;
+            let runtime = &alarms["sub1/alarm1"];
+            assert!(runtime.escalated);
+            assert_eq!(runtime.last.as_ref().unwrap().severity, AlarmSeverity::Low);
+        }
+
+        // The ack stops the clock
+        {
+            let mut alarms = emitter.runtime.lock().await;
+            apply_ack(alarms.get_mut("sub1/alarm1").unwrap());
+        }
+        escalation.check().await;
+        let alarms = emitter.runtime.lock().await;
+        let runtime = &alarms["sub1/alarm1"];
+        assert!(!runtime.escalated);
+        assert!(runtime.unack_since.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_restored() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}\n  alarm2: {}");
+        let escalation = escalation(&emitter);
+        let stored = |name: &str, escalated: bool| StoredState {
+            name: name.to_string(),
+            machine: StateMachine::new(IsaState::Unack, true),
+            shelved_until: None,
+            severity: Some(AlarmSeverity::Low.to_string()),
+            unack_since: Some(Utc::now() - chrono::Duration::seconds(400)),
+            escalated,
+            value: None,
+        };
+        emitter
+            .runtime
+            .restore_states(vec![stored("sub1/alarm1", true), stored("sub1/alarm2", false)])
+            .await;
+
+        // Only the alarm not escalated before the restart is
+        escalation.check().await;
+        let Ok(Message::Escalated(escalated)) = rx.try_recv() else {
+            panic!("alarm not escalated");
+        };
+        assert_eq!(escalated.alarm.name, "sub1/alarm2");
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::alarm::ack::AckOutcome;
+use crate::alarm::chattering::{ChatterAction, ChatterCount};
+use crate::alarm::{Alarm, AlarmLimit, AlarmSeverity, IsaState};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Alarm published on the broker and stored in the DB.
+///
+/// It serializes as the plain `Alarm` plus the extra information the
+/// server knows about the event, so older consumers keep working.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmEvent {
+    #[serde(flatten)]
+    pub alarm: Alarm,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<AlarmLimit>,
+
+    /// Measurement that moved the alarm, `value` being it rounded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub measurement: Option<f64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub isa_state: Option<IsaState>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shelved_until: Option<DateTime<Utc>>,
+
+    /// Operator who changed the state of the alarm.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// Reason given by the operator for a shelve, unshelve or change of
+    /// service.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    /// Comment of the operator on an ack.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+
+    /// When the operator acked the alarm on the client.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_timestamp: Option<DateTime<Utc>>,
+
+    /// The alarm is suppressed, it's stored but not annunciated.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub suppressed: bool,
+
+    /// The alarm was left unacknowledged for too long.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub escalated: bool,
+
+    /// Severity the escalation raised the alarm to, the alarm keeps its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalated_to: Option<AlarmSeverity>,
+}
+
+impl From<Alarm> for AlarmEvent {
+    fn from(alarm: Alarm) -> Self {
+        Self {
+            alarm,
+            limit: None,
+            measurement: None,
+            isa_state: None,
+            shelved_until: None,
+            user: None,
+            reason: None,
+            comment: None,
+            client_timestamp: None,
+            suppressed: false,
+            escalated: false,
+            escalated_to: None,
+        }
+    }
+}
+
+impl AlarmEvent {
+    /// Topic words of the alarm, its path and its severity, e.g.
+    /// `sub1.alarm1.high` for `sub1/alarm1`.
+    pub fn topic(&self) -> String {
+        // Dots would split the name in more words
+        let name = self.alarm.name.replace('.', "_").replace('/', ".");
+        let severity = self.alarm.severity.to_string().to_lowercase();
+        format!("{name}.{severity}")
+    }
+}
+
+/// Notice about the alarms that isn't an alarm state, e.g. an alarm found
+/// chattering. The kind is given by the `event` field.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notice {
+    /// The alarm toggled more than allowed in the chattering window.
+    Chattering {
+        alarm: String,
+        timestamp: DateTime<Utc>,
+        toggles: usize,
+        action: ChatterAction,
+    },
+    /// A chattering alarm settled down.
+    ChatteringEnd {
+        alarm: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// More alarms than the flood threshold were raised in the area.
+    FloodStart {
+        area: String,
+        timestamp: DateTime<Utc>,
+        alarms: usize,
+    },
+    /// The rate of alarms of the area went back under the threshold.
+    FloodEnd {
+        area: String,
+        timestamp: DateTime<Utc>,
+        /// Low severity alarms held back during the flood.
+        held: usize,
+    },
+    /// Result of a bulk ack, `alarm` being the pattern of the request.
+    AckResult {
+        alarm: String,
+        timestamp: DateTime<Utc>,
+        acked: usize,
+    },
+    /// An ack that had nothing to do.
+    AckIgnored {
+        alarm: String,
+        timestamp: DateTime<Utc>,
+        reason: AckOutcome,
+    },
+    /// A command sent to the server was rejected.
+    Error {
+        command: String,
+        alarm: String,
+        timestamp: DateTime<Utc>,
+        message: String,
+    },
+    /// Answer to the `chattering_report` command.
+    ChatteringReport {
+        timestamp: DateTime<Utc>,
+        alarms: Vec<ChatterCount>,
+    },
+}
+
+impl Notice {
+    /// Kind of the notice, as in the `event` field.
+    pub fn event(&self) -> &'static str {
+        match self {
+            Notice::Chattering { .. } => "chattering",
+            Notice::ChatteringEnd { .. } => "chattering_end",
+            Notice::FloodStart { .. } => "flood_start",
+            Notice::FloodEnd { .. } => "flood_end",
+            Notice::AckResult { .. } => "ack_result",
+            Notice::AckIgnored { .. } => "ack_ignored",
+            Notice::Error { .. } => "error",
+            Notice::ChatteringReport { .. } => "chattering_report",
+        }
+    }
+}
+
+/// Message published on the alarms exchange.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum Message {
+    Alarm(AlarmEvent),
+    /// Alarm annunciated again on the escalation routing key.
+    Escalated(AlarmEvent),
+    Notice(Notice),
+}
+
+impl From<AlarmEvent> for Message {
+    fn from(event: AlarmEvent) -> Self {
+        Message::Alarm(event)
+    }
+}
+
+impl From<Notice> for Message {
+    fn from(notice: Notice) -> Self {
+        Message::Notice(notice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmAck, AlarmSeverity, AlarmState};
+
+    #[test]
+    fn test_topic() {
+        let event = AlarmEvent::from(Alarm {
+            name: "sub1/pump.2/alarm1".to_string(),
+            timestamp: Utc::now(),
+            value: 1,
+            state: AlarmState::Set,
+            severity: AlarmSeverity::High,
+            ack: AlarmAck::NotAck,
+        });
+        assert_eq!(event.topic(), "sub1.pump_2.alarm1.high");
+    }
+
+    #[test]
+    fn test_measurement() {
+        let event = AlarmEvent {
+            measurement: Some(71234.56),
+            ..Alarm {
+                name: "sub1/temperature".to_string(),
+                timestamp: Utc::now(),
+                value: 71235,
+                state: AlarmState::Set,
+                severity: AlarmSeverity::High,
+                ack: AlarmAck::NotAck,
+            }
+            .into()
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["value"], 71235);
+        assert_eq!(json["measurement"], 71234.56);
+
+        let event = AlarmEvent {
+            measurement: None,
+            ..event
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert!(json.get("measurement").is_none());
+    }
+
+    #[test]
+    fn test_notice_event() {
+        let notices = [
+            Notice::FloodEnd {
+                area: "sub1".to_string(),
+                timestamp: Utc::now(),
+                held: 0,
+            },
+            Notice::AckIgnored {
+                alarm: "sub1/alarm1".to_string(),
+                timestamp: Utc::now(),
+                reason: AckOutcome::AlreadyAcked,
+            },
+            Notice::ChatteringReport {
+                timestamp: Utc::now(),
+                alarms: Vec::new(),
+            },
+        ];
+
+        for notice in notices {
+            let json = serde_json::to_value(&notice).unwrap();
+            assert_eq!(json["event"], notice.event());
+        }
+    }
+}
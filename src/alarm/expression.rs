@@ -0,0 +1,417 @@
+use crate::alarm::AlarmSeverity;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Alarm set while `condition` is true for the input of the trigger.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ExpressionAlarm {
+    pub condition: Expression,
+    pub severity: AlarmSeverity,
+}
+
+/// Boolean expression over the `value` of a trigger, e.g.
+/// `value > 80 && value < 120` or `(value & 0x04) != 0`.
+///
+/// Operators follow the Rust precedence. Every value is a float, comparisons
+/// and logical operators return `1` or `0` and anything non zero is true.
+/// Bitwise operators work on the integer part of their operands.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Expression {
+    pub fn evaluate(&self, value: f64) -> f64 {
+        self.root.evaluate(value)
+    }
+
+    pub fn is_true(&self, value: f64) -> bool {
+        self.evaluate(value) != 0.0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ParseError;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: source.chars().count(),
+        };
+        let root = parser.parse(0)?;
+
+        if let Some((_, position)) = parser.tokens.get(parser.pos) {
+            return Err(ParseError {
+                message: "unexpected token".to_string(),
+                position: *position,
+            });
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = ParseError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Not,
+    BitNot,
+}
+
+impl Op {
+    /// Binding power of the binary operators, higher binds tighter.
+    fn precedence(self) -> Option<u8> {
+        let p = match self {
+            Op::Or => 1,
+            Op::And => 2,
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => 3,
+            Op::BitOr => 4,
+            Op::BitXor => 5,
+            Op::BitAnd => 6,
+            Op::Shl | Op::Shr => 7,
+            Op::Add | Op::Sub => 8,
+            Op::Mul | Op::Div | Op::Rem => 9,
+            Op::Not | Op::BitNot => return None,
+        };
+        Some(p)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Value,
+    Op(Op),
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f64),
+    Value,
+    Unary(Op, Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, value: f64) -> f64 {
+        match self {
+            Node::Number(n) => *n,
+            Node::Value => value,
+            Node::Unary(op, node) => {
+                let v = node.evaluate(value);
+                match op {
+                    Op::Not => bool_to_f64(v == 0.0),
+                    Op::BitNot => !(v as i64) as f64,
+                    _ => -v,
+                }
+            }
+            Node::Binary(op, lhs, rhs) => {
+                let l = lhs.evaluate(value);
+                // Short circuit the logical operators
+                match op {
+                    Op::And if l == 0.0 => return 0.0,
+                    Op::Or if l != 0.0 => return 1.0,
+                    _ => {}
+                }
+                let r = rhs.evaluate(value);
+                match op {
+                    Op::Or | Op::And => bool_to_f64(r != 0.0),
+                    Op::BitOr => ((l as i64) | (r as i64)) as f64,
+                    Op::BitXor => ((l as i64) ^ (r as i64)) as f64,
+                    Op::BitAnd => ((l as i64) & (r as i64)) as f64,
+                    Op::Eq => bool_to_f64(l == r),
+                    Op::Ne => bool_to_f64(l != r),
+                    Op::Lt => bool_to_f64(l < r),
+                    Op::Le => bool_to_f64(l <= r),
+                    Op::Gt => bool_to_f64(l > r),
+                    Op::Ge => bool_to_f64(l >= r),
+                    Op::Shl => ((l as i64).wrapping_shl(r as u32)) as f64,
+                    Op::Shr => ((l as i64).wrapping_shr(r as u32)) as f64,
+                    Op::Add => l + r,
+                    Op::Sub => l - r,
+                    Op::Mul => l * r,
+                    Op::Div => l / r,
+                    Op::Rem => l % r,
+                    Op::Not | Op::BitNot => unreachable!("unary operator in a binary node"),
+                }
+            }
+        }
+    }
+}
+
+fn bool_to_f64(b: bool) -> f64 {
+    if b {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c.is_ascii_digit() || c == '.' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push((Token::Number(parse_number(&literal, start)?), start));
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            let token = match ident.as_str() {
+                "value" => Token::Value,
+                "true" => Token::Number(1.0),
+                "false" => Token::Number(0.0),
+                _ => {
+                    return Err(ParseError {
+                        message: format!("unknown identifier '{ident}'"),
+                        position: start,
+                    })
+                }
+            };
+            tokens.push((token, start));
+            continue;
+        }
+
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            ('|', Some('|')) => (Token::Op(Op::Or), 2),
+            ('&', Some('&')) => (Token::Op(Op::And), 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', Some('<')) => (Token::Op(Op::Shl), 2),
+            ('>', Some('>')) => (Token::Op(Op::Shr), 2),
+            ('|', _) => (Token::Op(Op::BitOr), 1),
+            ('^', _) => (Token::Op(Op::BitXor), 1),
+            ('&', _) => (Token::Op(Op::BitAnd), 1),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('+', _) => (Token::Op(Op::Add), 1),
+            ('-', _) => (Token::Op(Op::Sub), 1),
+            ('*', _) => (Token::Op(Op::Mul), 1),
+            ('/', _) => (Token::Op(Op::Div), 1),
+            ('%', _) => (Token::Op(Op::Rem), 1),
+            ('!', _) => (Token::Op(Op::Not), 1),
+            ('~', _) => (Token::Op(Op::BitNot), 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            _ => {
+                return Err(ParseError {
+                    message: format!("unexpected character '{c}'"),
+                    position: start,
+                })
+            }
+        };
+        tokens.push((token, start));
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(literal: &str, position: usize) -> Result<f64, ParseError> {
+    let parsed = if let Some(hex) = literal.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).map(|n| n as f64).ok()
+    } else if let Some(bin) = literal.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).map(|n| n as f64).ok()
+    } else {
+        literal.parse::<f64>().ok()
+    };
+
+    parsed.ok_or_else(|| ParseError {
+        message: format!("invalid number '{literal}'"),
+        position,
+    })
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    /// Precedence climbing over the binary operators binding tighter than
+    /// `min_precedence`.
+    fn parse(&mut self, min_precedence: u8) -> Result<Node, ParseError> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some((Token::Op(op), _)) = self.tokens.get(self.pos) {
+            let op = *op;
+            let precedence = match op.precedence() {
+                Some(p) if p > min_precedence => p,
+                _ => break,
+            };
+            self.pos += 1;
+            let rhs = self.parse(precedence)?;
+            lhs = Node::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, ParseError> {
+        let Some((token, position)) = self.tokens.get(self.pos).cloned() else {
+            return Err(ParseError {
+                message: "unexpected end of expression".to_string(),
+                position: self.end,
+            });
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => Ok(Node::Number(n)),
+            Token::Value => Ok(Node::Value),
+            Token::Op(op @ (Op::Not | Op::BitNot | Op::Sub)) => {
+                Ok(Node::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Token::Open => {
+                let node = self.parse(0)?;
+                match self.tokens.get(self.pos) {
+                    Some((Token::Close, _)) => {
+                        self.pos += 1;
+                        Ok(node)
+                    }
+                    _ => Err(ParseError {
+                        message: "missing ')'".to_string(),
+                        position,
+                    }),
+                }
+            }
+            _ => Err(ParseError {
+                message: "expected a value".to_string(),
+                position,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(source: &str) -> Expression {
+        source.parse().unwrap()
+    }
+
+    #[test]
+    fn test_range() {
+        let e = expr("value > 80 && value < 120");
+
+        assert!(!e.is_true(80.0));
+        assert!(e.is_true(80.5));
+        assert!(e.is_true(119.0));
+        assert!(!e.is_true(120.0));
+    }
+
+    #[test]
+    fn test_bitmask() {
+        let e = expr("(value & 0x04) != 0");
+        assert!(e.is_true(4.0));
+        assert!(e.is_true(7.0));
+        assert!(!e.is_true(3.0));
+
+        // Same as Rust, & binds tighter than !=
+        let e = expr("value & 0b100 != 0");
+        assert!(e.is_true(12.0));
+        assert!(!e.is_true(8.0));
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(expr("1 + 2 * 3").evaluate(0.0), 7.0);
+        assert_eq!(expr("(1 + 2) * 3").evaluate(0.0), 9.0);
+        assert_eq!(expr("-value - 2").evaluate(3.0), -5.0);
+        assert_eq!(expr("10 - 4 - 3").evaluate(0.0), 3.0);
+        assert_eq!(expr("1 << 2 | 1").evaluate(0.0), 5.0);
+        assert!(expr("!(value == 1) || false").is_true(2.0));
+        assert!(!expr("value < 0 || value > 10 && value < 20").is_true(30.0));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!("value >".parse::<Expression>().is_err());
+        assert!("(value > 1".parse::<Expression>().is_err());
+        assert!("value > 1)".parse::<Expression>().is_err());
+        assert!("temperature > 1".parse::<Expression>().is_err());
+        assert!("value $ 1".parse::<Expression>().is_err());
+        assert!("0xZZ".parse::<Expression>().is_err());
+
+        let err = "value > 1 &&".parse::<Expression>().unwrap_err();
+        assert_eq!(err.position, 12);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::alarm::{AlarmSeverity, Emitter, IsaState, Notice};
+use crate::config::FloodConfig;
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// Alarms raised in an operator area.
+#[derive(Debug, Default)]
+struct AreaRate {
+    raised: VecDeque<Instant>,
+    flooding: bool,
+    /// Low severity alarms held back during the flood.
+    held: Vec<String>,
+}
+
+impl AreaRate {
+    fn expire(&mut self, window: Duration, now: Instant) {
+        while let Some(first) = self.raised.front() {
+            if now.duration_since(*first) <= window {
+                break;
+            }
+            self.raised.pop_front();
+        }
+    }
+}
+
+/// Outcome of an alarm raised in an area.
+#[derive(Debug, Default)]
+pub struct Raised {
+    /// The area just entered the flood state.
+    pub flood_start: Option<Notice>,
+    /// The annunciation is held back until the flood clears.
+    pub hold: bool,
+}
+
+/// Tracks the rate of raised alarms of every operator area, the first part
+/// of the alarm name, across all the handlers.
+#[derive(Debug, Clone, Default)]
+pub struct Flood {
+    config: Option<FloodConfig>,
+    areas: Arc<Mutex<HashMap<String, AreaRate>>>,
+}
+
+impl Flood {
+    pub fn new(config: Option<FloodConfig>) -> Self {
+        Self {
+            config,
+            areas: Arc::default(),
+        }
+    }
+
+    pub fn area(name: &str) -> &str {
+        name.split('/').next().unwrap_or(name)
+    }
+
+    /// Counts an annunciated alarm.
+    pub async fn raise(&self, name: &str, severity: &AlarmSeverity) -> Raised {
+        self.raise_at(name, severity, Instant::now()).await
+    }
+
+    async fn raise_at(&self, name: &str, severity: &AlarmSeverity, now: Instant) -> Raised {
+        let Some(config) = &self.config else {
+            return Raised::default();
+        };
+
+        let area = Self::area(name);
+        let mut areas = self.areas.lock().await;
+        let rate = areas.entry(area.to_string()).or_default();
+        rate.raised.push_back(now);
+        rate.expire(config.window(), now);
+
+        let mut raised = Raised::default();
+        if !rate.flooding && rate.raised.len() > config.threshold {
+            rate.flooding = true;
+            raised.flood_start = Some(Notice::FloodStart {
+                area: area.to_string(),
+                timestamp: Utc::now(),
+                alarms: rate.raised.len(),
+            });
+        }
+
+        if rate.flooding && config.hold_low && *severity == AlarmSeverity::Low {
+            rate.held.push(name.to_string());
+            raised.hold = true;
+        }
+        raised
+    }
+
+    /// Ends the floods whose rate went back under the threshold. Returns the
+    /// notices with the alarms held back in each area.
+    async fn expire(&self, now: Instant) -> Vec<(Notice, Vec<String>)> {
+        let Some(config) = &self.config else {
+            return Vec::new();
+        };
+
+        let mut ended = Vec::new();
+        for (area, rate) in self.areas.lock().await.iter_mut() {
+            rate.expire(config.window(), now);
+            if !rate.flooding || rate.raised.len() > config.threshold {
+                continue;
+            }
+            rate.flooding = false;
+
+            let held = std::mem::take(&mut rate.held);
+            let notice = Notice::FloodEnd {
+                area: area.clone(),
+                timestamp: Utc::now(),
+                held: held.len(),
+            };
+            ended.push((notice, held));
+        }
+        ended
+    }
+}
+
+/// Ends the floods and annunciates the alarms held back during them.
+#[derive(Debug, Clone)]
+pub struct FloodMonitor {
+    emitter: Emitter,
+}
+
+impl FloodMonitor {
+    pub fn new(emitter: Emitter) -> Self {
+        Self { emitter }
+    }
+
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+
+            for (notice, held) in self.emitter.runtime.flood.expire(Instant::now()).await {
+                self.emitter.notify(notice).await;
+
+                // Only the alarms still waiting for the operator
+                let events: Vec<_> = {
+                    let alarms = self.emitter.runtime.lock().await;
+                    held.iter()
+                        .filter_map(|name| alarms.get(name).map(|runtime| (name, runtime)))
+                        .filter(|(_, runtime)| {
+                            matches!(
+                                runtime.machine.state(),
+                                IsaState::Unack | IsaState::RtnUnack
+                            )
+                        })
+                        .map(|(name, runtime)| runtime.state_event(name))
+                        .collect()
+                };
+                for event in events {
+                    self.emitter.annunciate(event).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flood() {
+        let flood = Flood::new(Some(FloodConfig {
+            threshold: 2,
+            window: 600.0,
+            hold_low: true,
+        }));
+        let start = Instant::now();
+
+        let raised = flood
+            .raise_at("sub1/alarm1", &AlarmSeverity::Low, start)
+            .await;
+        assert!(raised.flood_start.is_none());
+        assert!(!raised.hold);
+        flood
+            .raise_at("sub2/alarm1", &AlarmSeverity::Low, start)
+            .await;
+        flood
+            .raise_at("sub1/alarm2", &AlarmSeverity::High, start)
+            .await;
+
+        let raised = flood
+            .raise_at("sub1/alarm3", &AlarmSeverity::High, start)
+            .await;
+        assert!(matches!(
+            raised.flood_start,
+            Some(Notice::FloodStart { alarms: 3, .. })
+        ));
+        assert!(!raised.hold);
+
+        let raised = flood
+            .raise_at("sub1/alarm4", &AlarmSeverity::Low, start)
+            .await;
+        assert!(raised.flood_start.is_none());
+        assert!(raised.hold);
+
+        assert!(flood
+            .expire(start + Duration::from_secs(60))
+            .await
+            .is_empty());
+        let ended = flood.expire(start + Duration::from_secs(601)).await;
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0].1, vec!["sub1/alarm4".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let flood = Flood::default();
+
+        for _ in 0..10 {
+            let raised = flood.raise("sub1/alarm1", &AlarmSeverity::Low).await;
+            assert!(raised.flood_start.is_none());
+            assert!(!raised.hold);
+        }
+    }
+}
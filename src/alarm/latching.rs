@@ -0,0 +1,173 @@
+use crate::alarm::{AlarmAck, Emitter, IsaState, Target};
+use serde::Deserialize;
+
+/// Reset of a latching alarm. Older clients send only the name of the alarm
+/// as plain text.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ResetRequest {
+    pub alarm: String,
+
+    #[serde(default)]
+    pub user: Option<String>,
+
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl ResetRequest {
+    pub fn parse(payload: &str) -> Result<Self, serde_json::Error> {
+        let payload = payload.trim();
+        if payload.starts_with('{') {
+            return serde_json::from_str(payload);
+        }
+
+        Ok(Self {
+            alarm: payload.to_string(),
+            user: None,
+            reason: None,
+        })
+    }
+}
+
+/// Resets latching alarms on operator request.
+#[derive(Debug, Clone)]
+pub struct Latching {
+    emitter: Emitter,
+}
+
+impl Latching {
+    pub fn new(emitter: Emitter) -> Self {
+        Self { emitter }
+    }
+
+    /// Resets the latching alarm of the request. Its condition must be gone
+    /// and the alarm acked.
+    pub async fn reset(&self, request: ResetRequest) {
+        let name = &request.alarm;
+        let latched = {
+            let mut alarms = self.emitter.runtime.lock().await;
+            match alarms.get_mut(name) {
+                None => Err("unknown alarm"),
+                Some(runtime) if runtime.latched.is_none() => Err("not latched"),
+                Some(runtime) if runtime.machine.state() != IsaState::Acked => Err("not acked"),
+                Some(runtime) => Ok(runtime.latched.take().unwrap()),
+            }
+        };
+        let mut event = match latched {
+            Ok(event) => event,
+            Err(message) => {
+                self.emitter.reject("reset", name, message).await;
+                return;
+            }
+        };
+
+        event.alarm.ack = AlarmAck::Ack;
+        event.user = request.user;
+        event.reason = request.reason;
+        self.emitter.emit(Target::Reset, event).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::ack::apply_ack;
+    use crate::alarm::transition::tests::{emitter, event};
+    use crate::alarm::{AckOutcome, AlarmEvent, AlarmState, Message, Notice};
+    use tokio::sync::mpsc;
+
+    const CONFIG: &str = r#"
+        sub1:
+          alarm1: {}
+          alarm2:
+            latching: true
+    "#;
+
+    fn published(rx: &mut mpsc::Receiver<Message>) -> Option<AlarmEvent> {
+        match rx.try_recv() {
+            Ok(Message::Alarm(event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    async fn ack(emitter: &Emitter, name: &str) {
+        let mut alarms = emitter.runtime.lock().await;
+        assert_eq!(apply_ack(alarms.get_mut(name).unwrap()), AckOutcome::Acked);
+    }
+
+    #[test]
+    fn test_parse() {
+        let request = ResetRequest::parse(" sub1/alarm2\n").unwrap();
+        assert_eq!(request.alarm, "sub1/alarm2");
+        assert_eq!(request.user, None);
+
+        let payload = r#"{"alarm": "sub1/alarm2", "user": "jdoe", "reason": "valve replaced"}"#;
+        let request = ResetRequest::parse(payload).unwrap();
+        assert_eq!(request.user.as_deref(), Some("jdoe"));
+        assert_eq!(request.reason.as_deref(), Some("valve replaced"));
+        assert!(ResetRequest::parse("{\"user\": \"jdoe\"}").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_not_latching() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let definition = emitter.definitions.get("sub1/alarm1").cloned();
+
+        let set = event("sub1/alarm1", AlarmState::Set);
+        emitter.transition(definition.as_ref(), Target::Set(None), set).await;
+        assert_eq!(published(&mut rx).unwrap().isa_state, Some(IsaState::Unack));
+        ack(&emitter, "sub1/alarm1").await;
+
+        // The reset keeps the ack of the set and is reported right away
+        let mut reset = event("sub1/alarm1", AlarmState::Reset);
+        reset.alarm.ack = AlarmAck::Ack;
+        emitter.transition(definition.as_ref(), Target::Reset, reset).await;
+        let event = published(&mut rx).unwrap();
+        assert_eq!(event.alarm.state, AlarmState::Reset);
+        assert_eq!(event.alarm.ack, AlarmAck::Ack);
+        assert_eq!(event.isa_state, Some(IsaState::Normal));
+        assert!(emitter.runtime.lock().await["sub1/alarm1"].latched.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_latch_ack_reset() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let latching = Latching::new(emitter.clone());
+        let definition = emitter.definitions.get("sub1/alarm2").cloned();
+        let request = || ResetRequest {
+            alarm: "sub1/alarm2".to_string(),
+            user: Some("jdoe".to_string()),
+            reason: Some("valve replaced".to_string()),
+        };
+
+        let set = event("sub1/alarm2", AlarmState::Set);
+        emitter.transition(definition.as_ref(), Target::Set(None), set).await;
+        assert_eq!(published(&mut rx).unwrap().isa_state, Some(IsaState::Unack));
+
+        // The alarm stays set once its condition clears
+        let reset = event("sub1/alarm2", AlarmState::Reset);
+        emitter.transition(definition.as_ref(), Target::Reset, reset).await;
+        assert!(published(&mut rx).is_none());
+        assert!(emitter.runtime.lock().await["sub1/alarm2"].latched.is_some());
+
+        latching.reset(request()).await;
+        match rx.try_recv() {
+            Ok(Message::Notice(Notice::Error { message, .. })) => assert_eq!(message, "not acked"),
+            other => panic!("reset before the ack not rejected: {other:?}"),
+        }
+
+        ack(&emitter, "sub1/alarm2").await;
+        latching.reset(request()).await;
+        let event = published(&mut rx).unwrap();
+        assert_eq!(event.alarm.state, AlarmState::Reset);
+        assert_eq!(event.alarm.ack, AlarmAck::Ack);
+        assert_eq!(event.isa_state, Some(IsaState::Normal));
+        assert_eq!(event.user.as_deref(), Some("jdoe"));
+        assert_eq!(event.reason.as_deref(), Some("valve replaced"));
+
+        latching.reset(request()).await;
+        assert!(published(&mut rx).is_none());
+        let alarms = emitter.runtime.lock().await;
+        assert_eq!(alarms["sub1/alarm2"].reported, Some(Target::Reset));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::alarm::{Emitter, IsaEvent};
+use serde::Deserialize;
+
+/// Puts alarms out of service or back in service.
+#[derive(Debug, Deserialize)]
+pub struct ServiceRequest {
+    /// Name of an alarm, or a path prefix ending with `/` such as `sub1/`.
+    pub path: String,
+
+    pub user: String,
+
+    pub reason: String,
+}
+
+impl ServiceRequest {
+    pub fn matches(&self, alarm: &str) -> bool {
+        if self.path.ends_with('/') {
+            alarm.starts_with(&self.path)
+        } else {
+            alarm == self.path
+        }
+    }
+}
+
+/// Takes alarms out of service, e.g. during maintenance. The triggers of an
+/// out of service alarm only update its last value and condition.
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    emitter: Emitter,
+}
+
+impl Maintenance {
+    pub fn new(emitter: Emitter) -> Self {
+        Self { emitter }
+    }
+
+    pub async fn remove(&self, request: ServiceRequest) {
+        self.apply(request, IsaEvent::RemoveFromService).await;
+    }
+
+    pub async fn restore(&self, request: ServiceRequest) {
+        self.apply(request, IsaEvent::ReturnToService).await;
+    }
+
+    async fn apply(&self, request: ServiceRequest, isa_event: IsaEvent) {
+        let names: Vec<String> = self
+            .emitter
+            .definitions
+            .names()
+            .filter(|name| request.matches(name))
+            .collect();
+        let command = match isa_event {
+            IsaEvent::RemoveFromService => "remove_from_service",
+            _ => "return_to_service",
+        };
+        if names.is_empty() {
+            self.emitter
+                .reject(command, &request.path, "no alarm matches")
+                .await;
+            return;
+        }
+
+        let mut events = Vec::new();
+        let mut rejected = Vec::new();
+        {
+            let mut alarms = self.emitter.runtime.lock().await;
+            for name in names {
+                let runtime = alarms.entry(name.clone()).or_default();
+                match runtime.machine.handle(isa_event) {
+                    Ok(Some(_)) => {}
+                    // Already in the requested state
+                    Ok(None) => continue,
+                    Err(e) => {
+                        rejected.push((name, e.to_string()));
+                        continue;
+                    }
+                }
+                if let Some((_, handle)) = runtime.pending.take() {
+                    handle.abort();
+                }
+                runtime.shelved_until = None;
+
+                let mut event = runtime.state_event(&name);
+                event.user = Some(request.user.clone());
+                event.reason = Some(request.reason.clone());
+                events.push(event);
+            }
+        }
+
+        for (name, message) in rejected {
+            self.emitter.reject(command, &name, &message).await;
+        }
+        for event in events {
+            self.emitter.record(event).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::transition::tests::{emitter, event};
+    use crate::alarm::{AlarmState, IsaState, Message, Notice, Target};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_matches() {
+        let request = |path: &str| ServiceRequest {
+            path: path.to_string(),
+            user: "operator".to_string(),
+            reason: "maintenance".to_string(),
+        };
+
+        assert!(request("sub1/").matches("sub1/alarm1"));
+        assert!(request("sub1/").matches("sub1/plc/door_open"));
+        assert!(!request("sub1/").matches("sub10/alarm1"));
+        assert!(request("sub1/alarm1").matches("sub1/alarm1"));
+        assert!(!request("sub1/alarm1").matches("sub1/alarm10"));
+    }
+
+    #[tokio::test]
+    async fn test_return_to_service() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}\n  alarm2: {}");
+        let maintenance = Maintenance::new(emitter.clone());
+        let request = |path: &str| ServiceRequest {
+            path: path.to_string(),
+            user: "operator".to_string(),
+            reason: "maintenance".to_string(),
+        };
+        let trigger = |name: &'static str, target: Target| {
+            let emitter = emitter.clone();
+            async move {
+                let state = match target {
+                    Target::Set(_) => AlarmState::Set,
+                    Target::Reset => AlarmState::Reset,
+                };
+                emitter.transition(None, target, event(name, state)).await;
+            }
+        };
+        let next = |rx: &mut mpsc::Receiver<Message>| match rx.try_recv() {
+            Ok(Message::Alarm(event)) => Some((event.alarm.name, event.isa_state)),
+            _ => None,
+        };
+
+        trigger("sub1/alarm1", Target::Set(None)).await;
+        assert_eq!(next(&mut rx), Some(("sub1/alarm1".to_string(), Some(IsaState::Unack))));
+
+        maintenance.remove(request("sub1/")).await;
+        for _ in 0..2 {
+            let Ok(Message::Alarm(removed)) = rx.try_recv() else {
+                panic!("remove not recorded");
+            };
+            assert_eq!(removed.isa_state, Some(IsaState::OutOfService));
+            assert_eq!(removed.user.as_deref(), Some("operator"));
+        }
+
+        // The conditions change while out of service without anything
+        // published
+        trigger("sub1/alarm1", Target::Reset).await;
+        trigger("sub1/alarm2", Target::Set(None)).await;
+        assert_eq!(next(&mut rx), None);
+
+        maintenance.restore(request("sub1/alarm1")).await;
+        let Ok(Message::Alarm(restored)) = rx.try_recv() else {
+            panic!("return not recorded");
+        };
+        assert_eq!(restored.isa_state, Some(IsaState::Normal));
+        assert_eq!(restored.alarm.state, AlarmState::Reset);
+        assert_eq!(restored.reason.as_deref(), Some("maintenance"));
+
+        maintenance.restore(request("sub1/alarm2")).await;
+        assert_eq!(next(&mut rx), Some(("sub1/alarm2".to_string(), Some(IsaState::Unack))));
+        maintenance.restore(request("sub1/alarm2")).await;
+        match rx.try_recv() {
+            Ok(Message::Notice(Notice::Error { command, alarm, .. })) => {
+                assert_eq!(command, "return_to_service");
+                assert_eq!(alarm, "sub1/alarm2");
+            }
+            other => panic!("return of an alarm in service not rejected: {other:?}"),
+        }
+        maintenance.remove(request("sub2/")).await;
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Message::Notice(Notice::Error { .. }))
+        ));
+
+        // Back in service, the alarm is annunciated again
+        trigger("sub1/alarm1", Target::Set(None)).await;
+        assert_eq!(next(&mut rx), Some(("sub1/alarm1".to_string(), Some(IsaState::Unack))));
+    }
+
+    #[tokio::test]
+    async fn test_stale() {
+        let config = "sub1:\n  alarm1:\n    stale: {timeout: 10, severity: 0}";
+        let (emitter, mut rx) = emitter(config);
+        let maintenance = Maintenance::new(emitter.clone());
+
+        maintenance
+            .remove(ServiceRequest {
+                path: "sub1/alarm1/stale".to_string(),
+                user: "operator".to_string(),
+                reason: "sensor replaced".to_string(),
+            })
+            .await;
+        let Ok(Message::Alarm(removed)) = rx.try_recv() else {
+            panic!("remove not recorded");
+        };
+        assert_eq!(removed.alarm.name, "sub1/alarm1/stale");
+        assert_eq!(removed.isa_state, Some(IsaState::OutOfService));
+
+        // The watchdog raising it publishes nothing
+        emitter
+            .publish(event("sub1/alarm1/stale", AlarmState::Set))
+            .await;
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -3,99 +3,329 @@ use tokio::sync::mpsc;
 use async_channel;
 use chrono:: Utc;
 use cache::Cache;
+use serde::Deserialize;
+use tokio::time::Instant;
+
+pub mod ack;
+pub mod analog;
+pub mod bitmask;
+pub mod chattering;
+pub mod composite;
+pub mod definition;
+pub mod escalation;
+pub mod event;
+pub mod expression;
+pub mod flood;
+pub mod latching;
+pub mod maintenance;
+pub mod rate;
+pub mod runtime;
+pub mod shelving;
+pub mod state_machine;
+pub mod suppression;
+pub mod transition;
+pub mod watchdog;
 
 pub use alarm::{Alarm, AlarmSeverity, AlarmState, AlarmAck, AlarmTrigger, DigitalAlarm};
+pub use ack::{process_ack, AckOutcome, AckRequest};
+pub use analog::{AlarmLimit, AnalogLimits, Deadband, DeadbandMode};
+pub use bitmask::BitAlarm;
+pub use chattering::{ChatterAction, ChatterCount, ChatterMonitor, Chattering};
+pub use composite::{Composite, CompositeMode};
+pub use definition::{AlarmDefinition, Definitions};
+pub use escalation::Escalation;
+pub use event::{AlarmEvent, Message, Notice};
+pub use expression::{Expression, ExpressionAlarm};
+pub use flood::{Flood, FloodMonitor};
+pub use latching::{Latching, ResetRequest};
+pub use maintenance::{Maintenance, ServiceRequest};
+pub use rate::RateOfChange;
+pub use runtime::RuntimeStore;
+pub use shelving::{ShelveRequest, Shelving, UnshelveRequest};
+pub use state_machine::{IsaEvent, IsaState, StateMachine, TransitionError};
+pub use suppression::{Suppression, SuppressionRule};
+pub use transition::{Emitter, Target};
+pub use watchdog::{Stale, Watchdog};
+
+/// Trigger received from the broker. The input is read as a float so that
+/// analog measurements and discrete values share the same message.
+#[derive(Debug, Deserialize)]
+struct Trigger {
+    alarm: String,
+    input: f64,
+}
 
 #[derive(Debug)]
 pub struct AlarmHandler {
     rx_trg: async_channel::Receiver<String>,
-    tx_publisher: mpsc::Sender<Alarm>,
+    tx_publisher: mpsc::Sender<Message>,
     db: DB,
     cache: Cache,
+    definitions: Definitions,
+    runtime: RuntimeStore,
 }
 
 impl AlarmHandler {
-    pub fn new(rx_trg: async_channel::Receiver<String>, tx_publisher: mpsc::Sender<Alarm>, db: DB, cache: Cache) -> Self {
+    pub fn new(
+        rx_trg: async_channel::Receiver<String>,
+        tx_publisher: mpsc::Sender<Message>,
+        db: DB,
+        cache: Cache,
+        definitions: Definitions,
+        runtime: RuntimeStore,
+    ) -> Self {
         Self {
             rx_trg,
             tx_publisher,
             db,
             cache,
+            definitions,
+            runtime,
         }
     }
 
     pub async fn run(&mut self) {
 
         while let Ok(value) = self.rx_trg.recv().await {
-            let alm_trg: AlarmTrigger = serde_json::from_str(&value).unwrap();
+            let alm_trg: Trigger = serde_json::from_str(&value).unwrap();
+            self.seen(&alm_trg).await;
+            self.emitter().update_suppression(&alm_trg.alarm).await;
+            let definition = self.definitions.get(&alm_trg.alarm);
+
+            // Plain signals are only used by the suppression rules
+            if definition.is_none() && self.definitions.suppression().is_signal(&alm_trg.alarm) {
+                continue;
+            }
+
+            if let Some(definition) = definition {
+                if let Some(limits) = &definition.analog {
+                    self.process_analog(definition, limits, alm_trg.input).await;
+                    continue;
+                }
+                if let Some(rate) = &definition.rate {
+                    self.process_rate(definition, rate, alm_trg.input).await;
+                    continue;
+                }
+                if let Some(bits) = &definition.bits {
+                    self.process_bits(definition, bits, alm_trg.input).await;
+                    continue;
+                }
+                if let Some(expression) = &definition.expression {
+                    self.process_expression(definition, expression, alm_trg.input)
+                        .await;
+                    continue;
+                }
+            }
+
             let digi_alm = self.cache.get_alm_config(&alm_trg.alarm).await.unwrap();
 
-            if alm_trg.input == digi_alm.set {
-                let status = Alarm {
-                    name: digi_alm.name.clone(),
-                    timestamp: Utc::now(),
-                    value: alm_trg.input,
-                    state: AlarmState::Set,
-                    severity: digi_alm.severity,
-                    ack: AlarmAck::NotAck,
-                };
-                Self::send_event(&self.tx_publisher, status.clone()).await;
-                self.db.insert_alm(status).await;
-            } else if alm_trg.input == digi_alm.reset {
-                let alm = self
-                    .db
-                    .get_latest_alm(digi_alm.name.clone())
-                    .await;
-                match alm {
-                    Some(alm) => {
-                        if alm.state != AlarmState::Reset {
-                            let status = Alarm {
-                                name: digi_alm.name.clone(),
-                                timestamp: Utc::now(),
-                                value: digi_alm.reset,
-                                state: AlarmState::Reset,
-                                severity: digi_alm.severity,
-                                ack: if alm.ack == AlarmAck::Ack {
-                                    alm.ack
-                                } else {
-                                    AlarmAck::NotAck
-                                },
-                            };
-                            Self::send_event(&self.tx_publisher, status.clone())
-                                .await;
-                            self.db.insert_alm(status).await;
-                        }
-                    }
-                    _ => {
-                        eprintln!("Error reading the last status of {}", digi_alm.name);
-                    }
+            let target = if alm_trg.input == digi_alm.set as f64 {
+                Target::Set(None)
+            } else if alm_trg.input == digi_alm.reset as f64 {
+                Target::Reset
+            } else {
+                continue;
+            };
+            self.emitter()
+                .discrete(
+                    definition,
+                    &digi_alm.name,
+                    digi_alm.severity,
+                    target,
+                    alm_trg.input as i64,
+                )
+                .await;
+        }
+    }
+
+    /// Keeps the time and value of the last trigger of the alarm.
+    async fn seen(&self, trigger: &Trigger) {
+        let mut alarms = self.runtime.lock().await;
+        let runtime = alarms.entry(trigger.alarm.clone()).or_default();
+        runtime.last_seen = Some(Instant::now());
+        runtime.last_value = Some(trigger.input);
+    }
+
+    async fn process_analog(
+        &self,
+        definition: &AlarmDefinition,
+        limits: &AnalogLimits,
+        input: f64,
+    ) {
+        let name = &definition.name;
+        let (limit, reported) = {
+            let mut alarms = self.runtime.lock().await;
+            let runtime = alarms.entry(name.to_string()).or_default();
+            let limit = limits.evaluate(input, runtime.limit, definition.deadband.as_ref());
+            runtime.limit = limit;
+            (limit, runtime.reported)
+        };
+
+        match limit.and_then(|l| limits.get(l)) {
+            Some(crossed) => {
+                let severity = crossed.severity.clone();
+                self.raise(definition, limit, severity, input).await;
+            }
+            None => {
+                // The severity of a cleared alarm is the one of the limit it
+                // was set at.
+                let severity = match reported {
+                    Some(Target::Set(Some(l))) => limits.get(l).map(|l| l.severity.clone()),
+                    _ => None,
                 };
+                self.clear(definition, severity, input).await;
             }
         }
     }
 
-    async fn send_event(tx: &mpsc::Sender<Alarm>, status: Alarm) {
-        let _ = tx.send(status).await;
+    async fn process_rate(&self, definition: &AlarmDefinition, rate: &RateOfChange, input: f64) {
+        let exceeded = {
+            let mut alarms = self.runtime.lock().await;
+            let runtime = alarms.entry(definition.name.clone()).or_default();
+            rate.push(&mut runtime.samples, Instant::now(), input);
+            rate.exceeded(&runtime.samples)
+        };
+
+        if exceeded {
+            let severity = rate.severity.clone();
+            self.raise(definition, Some(AlarmLimit::Rate), severity, input)
+                .await;
+        } else {
+            let severity = Some(rate.severity.clone());
+            self.clear(definition, severity, input).await;
+        }
     }
-}
 
-pub async fn process_ack(
-    mut rx_ack: mpsc::Receiver<String>,
-    tx_publisher: mpsc::Sender<Alarm>,
-    db: DB,
-) {
-    while let Some(path) = rx_ack.recv().await {
-        db.send_ack(&path).await;
+    async fn process_expression(
+        &self,
+        definition: &AlarmDefinition,
+        expression: &ExpressionAlarm,
+        input: f64,
+    ) {
+        let severity = expression.severity.clone();
+        if expression.condition.is_true(input) {
+            self.raise(definition, None, severity, input).await;
+        } else {
+            self.clear(definition, Some(severity), input).await;
+        }
+    }
+
+    async fn process_bits(&self, definition: &AlarmDefinition, bits: &[BitAlarm], input: f64) {
+        let value = input as i64;
+
+        for bit in bits {
+            let Some(child) = self.definitions.get(&bit.alarm_name(&definition.name)) else {
+                continue;
+            };
+            let severity = bit.severity.clone();
+            if bit.is_active(value) {
+                self.raise(child, None, severity, input).await;
+            } else {
+                self.clear(child, Some(severity), input).await;
+            }
+        }
+    }
 
+    /// Sets the alarm, `limit` says which limit was crossed if any.
+    async fn raise(
+        &self,
+        definition: &AlarmDefinition,
+        limit: Option<AlarmLimit>,
+        severity: AlarmSeverity,
+        input: f64,
+    ) {
         let status = Alarm {
-            name: path,
+            name: definition.name.clone(),
             timestamp: Utc::now(),
-            value: i64::MAX,
-            state: AlarmState::Reset,
-            severity: AlarmSeverity::Low,
-            ack: AlarmAck::Ack,
+            value: input.round() as i64,
+            state: AlarmState::Set,
+            severity,
+            ack: AlarmAck::NotAck,
+        };
+        let status = AlarmEvent {
+            limit,
+            measurement: Some(input),
+            ..status.into()
         };
-        AlarmHandler::send_event(&tx_publisher, status).await;
+        self.transition(Some(definition), Target::Set(limit), status)
+            .await;
+    }
+
+    /// Resets the alarm if it was reported as set, keeping its ack.
+    async fn clear(
+        &self,
+        definition: &AlarmDefinition,
+        severity: Option<AlarmSeverity>,
+        input: f64,
+    ) {
+        self.emitter()
+            .clear(
+                Some(definition),
+                &definition.name,
+                severity,
+                input.round() as i64,
+                Some(input),
+            )
+            .await;
+    }
+
+    async fn transition(
+        &self,
+        definition: Option<&AlarmDefinition>,
+        target: Target,
+        status: AlarmEvent,
+    ) {
+        self.emitter().transition(definition, target, status).await;
+    }
+
+    fn emitter(&self) -> Emitter {
+        Emitter {
+            tx_publisher: self.tx_publisher.clone(),
+            db: self.db.clone(),
+            runtime: self.runtime.clone(),
+            definitions: self.definitions.clone(),
+        }
+    }
+
+    async fn send_event(tx: &mpsc::Sender<Message>, status: AlarmEvent) {
+        let _ = tx.send(status.into()).await;
+    }
+}
+
+/// Processes the operator commands other than the ack, received as
+/// `(routing key, payload)`.
+pub async fn process_commands(
+    mut rx_cmd: mpsc::Receiver<(String, String)>,
+    shelving: Shelving,
+    maintenance: Maintenance,
+    latching: Latching,
+    chatter: ChatterMonitor,
+) {
+    while let Some((key, payload)) = rx_cmd.recv().await {
+        match key.as_str() {
+            "reset" => match ResetRequest::parse(&payload) {
+                Ok(request) => latching.reset(request).await,
+                Err(e) => eprintln!("Invalid reset command '{payload}' - {e}"),
+            },
+            "chattering_report" => chatter.report().await,
+            "shelve" => match serde_json::from_str(&payload) {
+                Ok(request) => shelving.shelve(request).await,
+                Err(e) => eprintln!("Invalid shelve command '{payload}' - {e}"),
+            },
+            "unshelve" => match serde_json::from_str(&payload) {
+                Ok(request) => shelving.unshelve(request).await,
+                Err(e) => eprintln!("Invalid unshelve command '{payload}' - {e}"),
+            },
+            "remove_from_service" => match serde_json::from_str(&payload) {
+                Ok(request) => maintenance.remove(request).await,
+                Err(e) => eprintln!("Invalid remove_from_service command '{payload}' - {e}"),
+            },
+            "return_to_service" => match serde_json::from_str(&payload) {
+                Ok(request) => maintenance.restore(request).await,
+                Err(e) => eprintln!("Invalid return_to_service command '{payload}' - {e}"),
+            },
+            _ => eprintln!("Unknown command '{key}'"),
+        }
     }
 }
 
@@ -0,0 +1,120 @@
+use crate::alarm::AlarmSeverity;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Alarm on how fast a measurement changes instead of on its value.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RateOfChange {
+    /// Maximum rate, in units per second.
+    pub limit: f64,
+
+    /// Seconds of samples the rate is computed over.
+    pub window: f64,
+
+    pub severity: AlarmSeverity,
+}
+
+impl RateOfChange {
+    /// Adds a sample and drops the ones that fell out of the window.
+    pub fn push(&self, samples: &mut VecDeque<(Instant, f64)>, now: Instant, value: f64) {
+        samples.push_back((now, value));
+
+        let window = Duration::from_secs_f64(self.window);
+        while let Some((t, _)) = samples.front() {
+            if now.duration_since(*t) <= window {
+                break;
+            }
+            samples.pop_front();
+        }
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        let window = Duration::try_from_secs_f64(self.window).ok();
+        if window.filter(|w| !w.is_zero()).is_none() {
+            return Err(format!("invalid rate window {} of '{name}'", self.window));
+        }
+        Ok(())
+    }
+
+    pub fn exceeded(&self, samples: &VecDeque<(Instant, f64)>) -> bool {
+        match Self::rate(samples) {
+            Some(rate) => rate.abs() > self.limit,
+            None => false,
+        }
+    }
+
+    /// Rate of change between the oldest and the newest sample, in units per
+    /// second.
+    pub fn rate(samples: &VecDeque<(Instant, f64)>) -> Option<f64> {
+        let (first_t, first_v) = samples.front()?;
+        let (last_t, last_v) = samples.back()?;
+
+        let elapsed = last_t.duration_since(*first_t).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+
+        Some((last_v - first_v) / elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate() -> RateOfChange {
+        RateOfChange {
+            limit: 2.0,
+            window: 10.0,
+            severity: AlarmSeverity::High,
+        }
+    }
+
+    #[test]
+    fn test_rate() {
+        let rate = rate();
+        let start = Instant::now();
+        let mut samples = VecDeque::new();
+
+        rate.push(&mut samples, start, 10.0);
+        assert_eq!(RateOfChange::rate(&samples), None);
+        assert!(!rate.exceeded(&samples));
+
+        rate.push(&mut samples, start + Duration::from_secs(5), 20.0);
+        assert_eq!(RateOfChange::rate(&samples), Some(2.0));
+        assert!(!rate.exceeded(&samples));
+
+        rate.push(&mut samples, start + Duration::from_secs(6), 0.0);
+        assert_eq!(RateOfChange::rate(&samples), Some(-10.0 / 6.0));
+
+        rate.push(&mut samples, start + Duration::from_secs(7), 40.0);
+        assert!(rate.exceeded(&samples));
+    }
+
+    #[test]
+    fn test_window() {
+        let rate = rate();
+        let start = Instant::now();
+        let mut samples = VecDeque::new();
+
+        rate.push(&mut samples, start, 0.0);
+        rate.push(&mut samples, start + Duration::from_secs(8), 10.0);
+        rate.push(&mut samples, start + Duration::from_secs(15), 30.0);
+
+        assert_eq!(samples.len(), 2);
+        assert_eq!(RateOfChange::rate(&samples), Some(20.0 / 7.0));
+        assert!(rate.exceeded(&samples));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut rate = rate();
+        assert!(rate.validate("sub1/alarm1").is_ok());
+        for window in [0.0, -10.0, f64::NAN, f64::INFINITY, 1e300] {
+            rate.window = window;
+            assert!(rate.validate("sub1/alarm1").is_err());
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::alarm::{
+    Alarm, AlarmAck, AlarmEvent, AlarmLimit, AlarmSeverity, AlarmState, IsaEvent, IsaState,
+    StateMachine, Target, TransitionError,
+};
+use crate::alarm::chattering::ChatterState;
+use crate::alarm::flood::Flood;
+use crate::db::{StoredState, DB};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// In memory state of an alarm shared by all the handlers.
+#[derive(Debug, Default)]
+pub struct AlarmRuntime {
+    /// Analog limit the condition is currently at.
+    pub limit: Option<AlarmLimit>,
+    /// Last state published for the alarm.
+    pub reported: Option<Target>,
+    /// Transition waiting for its on/off delay to expire.
+    pub pending: Option<(Target, JoinHandle<()>)>,
+    /// Recent samples of a rate of change alarm.
+    pub samples: VecDeque<(Instant, f64)>,
+    /// Time and value of the last trigger.
+    pub last_seen: Option<Instant>,
+    pub last_value: Option<f64>,
+    /// Whether the `<alarm>/stale` alarm is set.
+    pub stale: bool,
+    pub machine: StateMachine,
+    pub shelved_until: Option<DateTime<Utc>>,
+    /// Last alarm published.
+    pub last: Option<Alarm>,
+    /// Reset of a latching alarm whose condition cleared, waiting for the
+    /// operator.
+    pub latched: Option<AlarmEvent>,
+    pub chatter: ChatterState,
+    /// When the alarm was first seen unacknowledged.
+    pub unack_since: Option<DateTime<Utc>>,
+    pub escalated: bool,
+    /// Severity read from the DB on start up, as stored.
+    pub stored_severity: Option<String>,
+}
+
+impl AlarmRuntime {
+    /// Event repeating the last alarm with the current state of the state
+    /// machine, for changes that don't come from the alarm condition.
+    pub fn state_event(&self, name: &str) -> AlarmEvent {
+        let alarm = match &self.last {
+            Some(last) => Alarm {
+                timestamp: Utc::now(),
+                ..last.clone()
+            },
+            None => Alarm {
+                name: name.to_string(),
+                timestamp: Utc::now(),
+                value: self.last_value.unwrap_or_default().round() as i64,
+                state: if self.machine.is_active() {
+                    AlarmState::Set
+                } else {
+                    AlarmState::Reset
+                },
+                severity: AlarmSeverity::Low,
+                ack: AlarmAck::NotAck,
+            },
+        };
+
+        let state = self.machine.state();
+        AlarmEvent {
+            measurement: self.last_value,
+            isa_state: Some(state),
+            shelved_until: self.shelved_until.filter(|_| state == IsaState::Shelved),
+            suppressed: state == IsaState::Suppressed,
+            ..alarm.into()
+        }
+    }
+
+    /// Shelves the alarm until `until` and returns the event to record.
+    pub fn shelve(
+        &mut self,
+        name: &str,
+        until: DateTime<Utc>,
+        reason: String,
+    ) -> Result<AlarmEvent, TransitionError> {
+        self.machine.handle(IsaEvent::Shelve)?;
+        self.shelved_until = Some(until);
+
+        let mut event = self.state_event(name);
+        event.reason = Some(reason);
+        Ok(event)
+    }
+
+    /// Starts the unacknowledged time of the alarm when it enters `unack`
+    /// and clears it when it leaves.
+    pub fn track_unack(&mut self, at: DateTime<Utc>) {
+        if self.machine.state() == IsaState::Unack {
+            self.unack_since.get_or_insert(at);
+        } else {
+            self.unack_since = None;
+            self.escalated = false;
+        }
+    }
+
+    /// Ack of the alarm, read from its state machine.
+    pub fn ack(&self) -> AlarmAck {
+        let acked = match self.machine.state() {
+            IsaState::Acked => true,
+            IsaState::Normal | IsaState::Unack | IsaState::RtnUnack => false,
+            _ => self.last.as_ref().is_some_and(|l| l.ack == AlarmAck::Ack),
+        };
+        if acked {
+            AlarmAck::Ack
+        } else {
+            AlarmAck::NotAck
+        }
+    }
+
+    /// Severity of the alarm among its `configured` ones: the last published
+    /// or, for the alarms restored on start up, the one matching the stored
+    /// severity.
+    pub fn severity(&self, configured: &[AlarmSeverity]) -> Option<AlarmSeverity> {
+        if let Some(last) = &self.last {
+            return Some(last.severity.clone());
+        }
+        configured
+            .iter()
+            .find(|s| self.stored_severity.as_deref() == Some(s.to_string().as_str()))
+            .or(configured.first())
+            .cloned()
+    }
+
+    /// Whether the last severity of the alarm is `severity`. The alarms
+    /// restored on start up only have the severity as stored in the DB.
+    pub fn severity_is(&self, severity: &AlarmSeverity) -> bool {
+        match &self.last {
+            Some(last) => last.severity == *severity,
+            None => self.stored_severity == Some(severity.to_string()),
+        }
+    }
+
+    /// Suppresses or unsuppresses the alarm. Returns the event to record if
+    /// the state changed.
+    pub fn suppress(&mut self, name: &str, suppressed: bool) -> Option<AlarmEvent> {
+        if (self.machine.state() == IsaState::Suppressed) == suppressed {
+            return None;
+        }
+
+        let event = if suppressed {
+            IsaEvent::Suppress
+        } else {
+            IsaEvent::Unsuppress
+        };
+        // Shelved and out of service alarms are left as they are
+        self.machine.handle(event).ok()?;
+        Some(self.state_event(name))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeStore {
+    alarms: Arc<Mutex<HashMap<String, AlarmRuntime>>>,
+    /// Rate of raised alarms per area.
+    pub flood: Flood,
+}
+
+impl RuntimeStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_flood(flood: Flood) -> Self {
+        Self {
+            flood,
+            ..Self::default()
+        }
+    }
+
+    pub async fn lock(&self) -> MutexGuard<'_, HashMap<String, AlarmRuntime>> {
+        self.alarms.lock().await
+    }
+
+    /// Restores the state machine of the alarms from their last row in the DB.
+    pub async fn restore(&self, db: &DB) {
+        self.restore_states(db.get_latest_states().await).await;
+    }
+
+    pub async fn restore_states(&self, states: Vec<StoredState>) {
+        let mut alarms = self.lock().await;
+
+        for state in states {
+            let runtime = alarms.entry(state.name).or_default();
+            if state.machine.is_active() {
+                runtime.reported = Some(Target::Set(None));
+            }
+            runtime.machine = state.machine;
+            runtime.shelved_until = state.shelved_until;
+            runtime.unack_since = state.unack_since;
+            runtime.escalated = state.escalated;
+            runtime.stored_severity = state.severity;
+            runtime.last_value = state.value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::transition::tests::{emitter, event};
+
+    #[tokio::test]
+    async fn test_restore_shelved() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}");
+        let until = Utc::now() + chrono::Duration::minutes(10);
+        emitter
+            .runtime
+            .restore_states(vec![StoredState {
+                name: "sub1/alarm1".to_string(),
+                machine: StateMachine::new(IsaState::Shelved, false),
+                shelved_until: Some(until),
+                severity: Some(AlarmSeverity::High.to_string()),
+                unack_since: None,
+                escalated: false,
+                value: Some(3.5),
+            }])
+            .await;
+
+        {
+            let alarms = emitter.runtime.lock().await;
+            let restored = alarms["sub1/alarm1"].state_event("sub1/alarm1");
+            assert_eq!(restored.isa_state, Some(IsaState::Shelved));
+            assert_eq!(restored.shelved_until, Some(until));
+            assert_eq!(restored.measurement, Some(3.5));
+        }
+
+        // Events of the shelved alarm keep the end of the shelve
+        emitter.publish(event("sub1/alarm1", AlarmState::Set)).await;
+        assert!(rx.try_recv().is_err());
+        let alarms = emitter.runtime.lock().await;
+        let runtime = &alarms["sub1/alarm1"];
+        assert!(runtime.machine.is_active());
+        assert_eq!(runtime.state_event("sub1/alarm1").shelved_until, Some(until));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::alarm::{Emitter, IsaEvent};
+use chrono::Utc;
+use serde::Deserialize;
+use std::time::Duration;
+
+#[derive(Debug, Deserialize)]
+pub struct ShelveRequest {
+    pub alarm: String,
+
+    /// Seconds the alarm stays shelved.
+    pub duration: f64,
+
+    pub reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnshelveRequest {
+    pub alarm: String,
+
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// Shelves alarms on operator request and unshelves them once the shelve
+/// expires. A shelved alarm is still evaluated and stored but not published.
+#[derive(Debug, Clone)]
+pub struct Shelving {
+    emitter: Emitter,
+}
+
+impl Shelving {
+    pub fn new(emitter: Emitter) -> Self {
+        Self { emitter }
+    }
+
+    pub async fn shelve(&self, request: ShelveRequest) {
+        if !self.emitter.definitions.contains(&request.alarm) {
+            self.emitter
+                .reject("shelve", &request.alarm, "unknown alarm")
+                .await;
+            return;
+        }
+
+        // Negative, NaN and durations past the end of time are rejected
+        let until = Duration::try_from_secs_f64(request.duration)
+            .ok()
+            .filter(|d| !d.is_zero())
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .and_then(|d| Utc::now().checked_add_signed(d));
+        let Some(until) = until else {
+            let message = format!("invalid duration {}", request.duration);
+            self.emitter
+                .reject("shelve", &request.alarm, &message)
+                .await;
+            return;
+        };
+
+        let event = {
+            let mut alarms = self.emitter.runtime.lock().await;
+            let runtime = alarms.entry(request.alarm.clone()).or_default();
+            runtime.shelve(&request.alarm, until, request.reason)
+        };
+
+        match event {
+            Ok(event) => self.emitter.record(event).await,
+            Err(e) => {
+                let message = e.to_string();
+                self.emitter
+                    .reject("shelve", &request.alarm, &message)
+                    .await;
+            }
+        }
+    }
+
+    pub async fn unshelve(&self, request: UnshelveRequest) {
+        let event = {
+            let mut alarms = self.emitter.runtime.lock().await;
+            match alarms.get_mut(&request.alarm) {
+                None => Err("not shelved".to_string()),
+                Some(runtime) => match runtime.machine.handle(IsaEvent::Unshelve) {
+                    Err(e) => Err(e.to_string()),
+                    Ok(_) => {
+                        runtime.shelved_until = None;
+                        let mut event = runtime.state_event(&request.alarm);
+                        event.reason = request.reason;
+                        Ok(event)
+                    }
+                },
+            }
+        };
+
+        match event {
+            Ok(event) => self.emitter.record(event).await,
+            Err(message) => {
+                self.emitter
+                    .reject("unshelve", &request.alarm, &message)
+                    .await;
+            }
+        }
+    }
+
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+            self.expire().await;
+        }
+    }
+
+    /// Unshelves the alarms whose shelve expired.
+    async fn expire(&self) {
+        let now = Utc::now();
+        let expired: Vec<String> = self
+            .emitter
+            .runtime
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, runtime)| runtime.shelved_until.is_some_and(|t| t <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for alarm in expired {
+            self.unshelve(UnshelveRequest {
+                alarm,
+                reason: Some("shelve expired".to_string()),
+            })
+            .await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::transition::tests::emitter;
+    use crate::alarm::{IsaState, Message, Notice, StateMachine};
+    use crate::db::StoredState;
+
+    fn shelve(duration: f64) -> ShelveRequest {
+        ShelveRequest {
+            alarm: "sub1/alarm1".to_string(),
+            duration,
+            reason: "sensor being replaced".to_string(),
+        }
+    }
+
+    fn unshelve() -> UnshelveRequest {
+        UnshelveRequest {
+            alarm: "sub1/alarm1".to_string(),
+            reason: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_duration() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}");
+        let shelving = Shelving::new(emitter.clone());
+
+        for duration in [-1.0, 0.0, f64::NAN, f64::INFINITY, 1e300] {
+            shelving.shelve(shelve(duration)).await;
+            match rx.try_recv() {
+                Ok(Message::Notice(Notice::Error { command, .. })) => assert_eq!(command, "shelve"),
+                other => panic!("{duration} not rejected: {other:?}"),
+            }
+        }
+        assert!(emitter.runtime.lock().await.get("sub1/alarm1").is_none());
+
+        let mut unknown = shelve(60.0);
+        unknown.alarm = "sub1/missing".to_string();
+        shelving.shelve(unknown).await;
+        assert!(matches!(rx.try_recv(), Ok(Message::Notice(Notice::Error { .. }))));
+    }
+
+    #[tokio::test]
+    async fn test_shelve_unshelve() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}");
+        let shelving = Shelving::new(emitter.clone());
+
+        shelving.shelve(shelve(60.0)).await;
+        let Ok(Message::Alarm(event)) = rx.try_recv() else {
+            panic!("shelve not recorded");
+        };
+        assert_eq!(event.isa_state, Some(IsaState::Shelved));
+        let until = event.shelved_until.unwrap();
+        assert!(until > Utc::now() + chrono::Duration::seconds(59));
+        assert_eq!(event.reason.as_deref(), Some("sensor being replaced"));
+
+        // Not expired yet
+        shelving.expire().await;
+        assert!(rx.try_recv().is_err());
+
+        shelving.unshelve(unshelve()).await;
+        let Ok(Message::Alarm(event)) = rx.try_recv() else {
+            panic!("unshelve not recorded");
+        };
+        assert_eq!(event.isa_state, Some(IsaState::Normal));
+        assert_eq!(event.shelved_until, None);
+
+        shelving.unshelve(unshelve()).await;
+        match rx.try_recv() {
+            Ok(Message::Notice(Notice::Error { command, .. })) => assert_eq!(command, "unshelve"),
+            other => panic!("unshelve of an alarm not shelved not rejected: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expired_across_restart() {
+        let (emitter, mut rx) = emitter("sub1:\n  alarm1: {}");
+        let shelving = Shelving::new(emitter.clone());
+
+        // Shelve that ended while the server was down, the condition active
+        emitter
+            .runtime
+            .restore_states(vec![StoredState {
+                name: "sub1/alarm1".to_string(),
+                machine: StateMachine::new(IsaState::Shelved, true),
+                shelved_until: Some(Utc::now() - chrono::Duration::seconds(5)),
+                severity: None,
+                unack_since: None,
+                escalated: false,
+                value: None,
+            }])
+            .await;
+
+        shelving.expire().await;
+        let Ok(Message::Alarm(event)) = rx.try_recv() else {
+            panic!("expiry not recorded");
+        };
+        assert_eq!(event.isa_state, Some(IsaState::Unack));
+        assert_eq!(event.reason.as_deref(), Some("shelve expired"));
+        assert!(emitter.runtime.lock().await["sub1/alarm1"].shelved_until.is_none());
+
+        shelving.expire().await;
+        assert!(rx.try_recv().is_err());
+    }
+}
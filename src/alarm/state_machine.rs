@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Alarm states modelled on ISA-18.2 / IEC 62682.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsaState {
+    /// Condition not active and nothing left to acknowledge.
+    #[default]
+    Normal,
+    /// Condition active and not acknowledged.
+    Unack,
+    /// Condition active and acknowledged.
+    Acked,
+    /// Condition returned to normal before being acknowledged.
+    RtnUnack,
+    /// Temporarily removed from annunciation by an operator.
+    Shelved,
+    /// Removed from annunciation by the process state.
+    Suppressed,
+    /// Removed from service, e.g. for maintenance.
+    OutOfService,
+}
+
+impl fmt::Display for IsaState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IsaState::Normal => "normal",
+            IsaState::Unack => "unack",
+            IsaState::Acked => "acked",
+            IsaState::RtnUnack => "rtn_unack",
+            IsaState::Shelved => "shelved",
+            IsaState::Suppressed => "suppressed",
+            IsaState::OutOfService => "out_of_service",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsaEvent {
+    /// The alarm condition became active.
+    Activate,
+    /// The alarm condition went away.
+    Clear,
+    Ack,
+    Shelve,
+    Unshelve,
+    Suppress,
+    Unsuppress,
+    RemoveFromService,
+    ReturnToService,
+}
+
+/// Event not allowed in the current state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionError {
+    pub state: IsaState,
+    pub event: IsaEvent,
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} is not allowed in the {} state", self.event, self.state)
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+/// State of an alarm plus its process condition. The condition is kept
+/// while the alarm is shelved, suppressed or out of service so the right
+/// state is restored when it comes back.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StateMachine {
+    state: IsaState,
+    active: bool,
+}
+
+impl StateMachine {
+    pub fn new(state: IsaState, active: bool) -> Self {
+        Self { state, active }
+    }
+
+    pub fn state(&self) -> IsaState {
+        self.state
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Applies `event` and returns the new state, or `None` if the state
+    /// didn't change.
+    pub fn handle(&mut self, event: IsaEvent) -> Result<Option<IsaState>, TransitionError> {
+        use IsaEvent::*;
+        use IsaState::*;
+
+        match event {
+            Activate => self.active = true,
+            Clear => self.active = false,
+            _ => {}
+        }
+
+        let next = match (self.state, event) {
+            (Normal | RtnUnack, Activate) => Unack,
+            (Unack, Clear) => RtnUnack,
+            (Acked, Clear) => Normal,
+            (Normal | Unack | Acked | RtnUnack, Activate | Clear) => self.state,
+            (Unack, Ack) => Acked,
+            (RtnUnack, Ack) => Normal,
+
+            (Normal | Unack | Acked | RtnUnack, Shelve) => Shelved,
+            (Normal | Unack | Acked | RtnUnack, Suppress) => Suppressed,
+            (Normal | Unack | Acked | RtnUnack | Shelved | Suppressed, RemoveFromService) => {
+                OutOfService
+            }
+
+            // Shelving again only extends the shelve
+            (Shelved, Shelve) => Shelved,
+            (Shelved, Unshelve) | (Suppressed, Unsuppress) | (OutOfService, ReturnToService) => {
+                self.returned()
+            }
+            (Shelved | Suppressed | OutOfService, Activate | Clear) => self.state,
+
+            (state, event) => return Err(TransitionError { state, event }),
+        };
+
+        if next == self.state {
+            return Ok(None);
+        }
+        self.state = next;
+        Ok(Some(next))
+    }
+
+    /// State taken when an alarm goes back to annunciating.
+    fn returned(&self) -> IsaState {
+        if self.active {
+            IsaState::Unack
+        } else {
+            IsaState::Normal
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(state: IsaState, active: bool) -> StateMachine {
+        StateMachine::new(state, active)
+    }
+
+    #[test]
+    fn test_annunciation_cycle() {
+        let mut m = StateMachine::default();
+
+        assert_eq!(m.handle(IsaEvent::Activate), Ok(Some(IsaState::Unack)));
+        assert_eq!(m.handle(IsaEvent::Ack), Ok(Some(IsaState::Acked)));
+        assert_eq!(m.handle(IsaEvent::Clear), Ok(Some(IsaState::Normal)));
+        assert!(!m.is_active());
+    }
+
+    #[test]
+    fn test_return_to_normal_unacked() {
+        let mut m = StateMachine::default();
+
+        assert_eq!(m.handle(IsaEvent::Activate), Ok(Some(IsaState::Unack)));
+        assert_eq!(m.handle(IsaEvent::Clear), Ok(Some(IsaState::RtnUnack)));
+        assert_eq!(m.handle(IsaEvent::Activate), Ok(Some(IsaState::Unack)));
+        assert_eq!(m.handle(IsaEvent::Clear), Ok(Some(IsaState::RtnUnack)));
+        assert_eq!(m.handle(IsaEvent::Ack), Ok(Some(IsaState::Normal)));
+    }
+
+    #[test]
+    fn test_repeated_events() {
+        let mut m = machine(IsaState::Unack, true);
+        assert_eq!(m.handle(IsaEvent::Activate), Ok(None));
+
+        let mut m = StateMachine::default();
+        assert_eq!(m.handle(IsaEvent::Clear), Ok(None));
+    }
+
+    #[test]
+    fn test_ack_guards() {
+        let err = machine(IsaState::Normal, false).handle(IsaEvent::Ack).unwrap_err();
+        assert_eq!(err.state, IsaState::Normal);
+
+        assert!(machine(IsaState::Acked, true).handle(IsaEvent::Ack).is_err());
+        assert!(machine(IsaState::Shelved, true).handle(IsaEvent::Ack).is_err());
+        assert!(machine(IsaState::OutOfService, true).handle(IsaEvent::Ack).is_err());
+    }
+
+    #[test]
+    fn test_shelve() {
+        let mut m = machine(IsaState::Unack, true);
+
+        assert_eq!(m.handle(IsaEvent::Shelve), Ok(Some(IsaState::Shelved)));
+        assert_eq!(m.handle(IsaEvent::Shelve), Ok(None));
+        assert_eq!(m.handle(IsaEvent::Clear), Ok(None));
+        assert_eq!(m.state(), IsaState::Shelved);
+        assert_eq!(m.handle(IsaEvent::Unshelve), Ok(Some(IsaState::Normal)));
+
+        let mut m = machine(IsaState::Normal, false);
+        m.handle(IsaEvent::Shelve).unwrap();
+        m.handle(IsaEvent::Activate).unwrap();
+        assert_eq!(m.handle(IsaEvent::Unshelve), Ok(Some(IsaState::Unack)));
+
+        assert!(machine(IsaState::Normal, false).handle(IsaEvent::Unshelve).is_err());
+    }
+
+    #[test]
+    fn test_suppress() {
+        let mut m = machine(IsaState::Acked, true);
+
+        assert_eq!(m.handle(IsaEvent::Suppress), Ok(Some(IsaState::Suppressed)));
+        assert!(m.handle(IsaEvent::Shelve).is_err());
+        assert_eq!(m.handle(IsaEvent::Unsuppress), Ok(Some(IsaState::Unack)));
+    }
+
+    #[test]
+    fn test_out_of_service() {
+        let mut m = machine(IsaState::Shelved, false);
+
+        assert_eq!(m.handle(IsaEvent::RemoveFromService), Ok(Some(IsaState::OutOfService)));
+        assert!(m.handle(IsaEvent::Unshelve).is_err());
+        assert!(m.handle(IsaEvent::Suppress).is_err());
+        assert_eq!(m.handle(IsaEvent::Activate), Ok(None));
+        assert_eq!(m.handle(IsaEvent::ReturnToService), Ok(Some(IsaState::Unack)));
+
+        assert!(machine(IsaState::Normal, false).handle(IsaEvent::ReturnToService).is_err());
+    }
+}
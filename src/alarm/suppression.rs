@@ -0,0 +1,178 @@
+use crate::alarm::runtime::AlarmRuntime;
+use crate::alarm::{Expression, Target};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+
+/// Suppresses the alarms matching `alarms` while the `when` condition holds,
+/// e.g. every alarm of a unit while the unit is stopped.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SuppressionRule {
+    /// Name of the suppressed alarms, `*` matches any part of the name.
+    pub alarms: String,
+    pub when: Condition,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    /// The last value received for the `signal` trigger makes `condition`
+    /// true. The signal doesn't need to be an alarm.
+    Signal {
+        signal: String,
+        condition: Expression,
+    },
+    /// The `alarm` is set, or reset if `active` is false.
+    Alarm {
+        alarm: String,
+        #[serde(default = "default_active")]
+        active: bool,
+    },
+}
+
+fn default_active() -> bool {
+    true
+}
+
+impl SuppressionRule {
+    /// Trigger or alarm the rule depends on.
+    pub fn source(&self) -> &str {
+        match &self.when {
+            Condition::Signal { signal, .. } => signal,
+            Condition::Alarm { alarm, .. } => alarm,
+        }
+    }
+
+    pub fn applies_to(&self, alarm: &str) -> bool {
+        // A rule never suppresses its own source
+        alarm != self.source() && matches(&self.alarms, alarm)
+    }
+
+    pub fn holds(&self, alarms: &HashMap<String, AlarmRuntime>) -> bool {
+        let source = alarms.get(self.source());
+        match &self.when {
+            Condition::Signal { condition, .. } => source
+                .and_then(|runtime| runtime.last_value)
+                .is_some_and(|value| condition.is_true(value)),
+            Condition::Alarm { active, .. } => {
+                let set = matches!(
+                    source.and_then(|runtime| runtime.reported),
+                    Some(Target::Set(_))
+                );
+                set == *active
+            }
+        }
+    }
+}
+
+/// State based suppression rules, loaded from their own file.
+#[derive(Debug, Clone, Default)]
+pub struct Suppression {
+    rules: Arc<Vec<SuppressionRule>>,
+}
+
+impl Suppression {
+    pub fn load(path: &str) -> Self {
+        let source = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("suppression file not found. Path: '{path}'"));
+
+        Self::from_yaml(&source).expect("Invalid suppression file")
+    }
+
+    pub fn from_yaml(source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let rules: Vec<SuppressionRule> = serde_yaml::from_str(source)?;
+        Ok(Self {
+            rules: Arc::new(rules),
+        })
+    }
+
+    /// Whether `name` is a plain signal used by a rule rather than an alarm.
+    pub fn is_signal(&self, name: &str) -> bool {
+        self.rules
+            .iter()
+            .any(|r| matches!(&r.when, Condition::Signal { signal, .. } if signal == name))
+    }
+
+    /// Rules depending on the trigger or alarm `source`.
+    pub fn rules_on<'a>(&'a self, source: &'a str) -> impl Iterator<Item = &'a SuppressionRule> {
+        self.rules.iter().filter(move |r| r.source() == source)
+    }
+
+    pub fn is_suppressed(&self, alarm: &str, alarms: &HashMap<String, AlarmRuntime>) -> bool {
+        self.rules
+            .iter()
+            .any(|r| r.applies_to(alarm) && r.holds(alarms))
+    }
+}
+
+/// Matches `name` against `pattern`, where `*` stands for any sequence of
+/// characters.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // `split` always yields at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("sub2/*", "sub2/alarm1"));
+        assert!(matches("sub2/*", "sub2/plc/door_open"));
+        assert!(!matches("sub2/*", "sub1/alarm1"));
+        assert!(matches("sub1/alarm1", "sub1/alarm1"));
+        assert!(!matches("sub1/alarm1", "sub1/alarm10"));
+        assert!(matches("*/stale", "sub3/pressure/stale"));
+        assert!(matches("sub*/alarm*", "sub1/alarm2"));
+        assert!(!matches("sub*/alarm*", "sub1/temperature"));
+        assert!(!matches("a*a", "a"));
+        assert!(matches("*", "anything"));
+    }
+
+    #[test]
+    fn test_rules() {
+        let config = r#"
+            - alarms: "sub2/*"
+              when:
+                signal: sub2/running
+                condition: "value == 0"
+            - alarms: sub1/alarm2
+              when:
+                alarm: sub1/alarm1
+        "#;
+        let suppression = Suppression::from_yaml(config).unwrap();
+        assert!(suppression.is_signal("sub2/running"));
+        assert!(!suppression.is_signal("sub1/alarm1"));
+
+        let mut alarms: HashMap<String, AlarmRuntime> = HashMap::new();
+        assert!(!suppression.is_suppressed("sub2/alarm1", &alarms));
+
+        alarms.entry("sub2/running".to_string()).or_default().last_value = Some(0.0);
+        assert!(suppression.is_suppressed("sub2/alarm1", &alarms));
+        assert!(!suppression.is_suppressed("sub2/running", &alarms));
+        assert!(!suppression.is_suppressed("sub1/alarm2", &alarms));
+
+        alarms.entry("sub1/alarm1".to_string()).or_default().reported = Some(Target::Set(None));
+        assert!(suppression.is_suppressed("sub1/alarm2", &alarms));
+        assert_eq!(suppression.rules_on("sub1/alarm1").count(), 1);
+    }
+}
@@ -0,0 +1,736 @@
+use crate::alarm::{
+    Alarm, AlarmAck, AlarmDefinition, AlarmEvent, AlarmHandler, AlarmLimit, AlarmSeverity,
+    AlarmState, ChatterAction, Definitions, IsaEvent, IsaState, Message, Notice, RuntimeStore,
+};
+use crate::db::DB;
+use chrono::Utc;
+use std::collections::VecDeque;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// State an alarm is asked to move to by its condition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Set(Option<AlarmLimit>),
+    Reset,
+}
+
+/// Publishes and stores the alarm events. It can be moved into the tasks
+/// waiting on the on/off delays.
+#[derive(Debug, Clone)]
+pub struct Emitter {
+    pub tx_publisher: mpsc::Sender<Message>,
+    pub db: DB,
+    pub runtime: RuntimeStore,
+    pub definitions: Definitions,
+}
+
+impl Emitter {
+    /// Reports the alarm as moved to `target`.
+    pub async fn emit(&self, target: Target, mut event: AlarmEvent) {
+        {
+            let mut alarms = self.runtime.lock().await;
+            let runtime = alarms.entry(event.alarm.name.clone()).or_default();
+            runtime.reported = Some(target);
+            runtime.pending = None;
+        }
+
+        event.alarm.timestamp = Utc::now();
+        self.publish(event).await;
+    }
+
+    /// Moves the alarm to `target` once the on/off delay of its definition
+    /// expired. A transition still waiting on its delay is cancelled if the
+    /// condition goes back to the last reported state.
+    pub async fn transition(
+        &self,
+        definition: Option<&AlarmDefinition>,
+        target: Target,
+        status: AlarmEvent,
+    ) {
+        let delay = definition.and_then(|d| match target {
+            Target::Set(_) => d.on_delay(),
+            Target::Reset => d.off_delay(),
+        });
+
+        let mut alarms = self.runtime.lock().await;
+        let runtime = alarms.entry(status.alarm.name.clone()).or_default();
+
+        if let Some((pending, handle)) = runtime.pending.take() {
+            if pending == target && !handle.is_finished() {
+                runtime.pending = Some((pending, handle));
+                return;
+            }
+            handle.abort();
+        }
+
+        // A chattering alarm waits longer before being set again
+        let delay = match (target, definition.and_then(|d| d.chattering.as_ref())) {
+            (Target::Set(_), Some(chattering))
+                if runtime.chatter.chattering && chattering.action == ChatterAction::Delay =>
+            {
+                delay.max(Some(chattering.duration()))
+            }
+            _ => delay,
+        };
+
+        if definition.is_some_and(|d| d.latching) {
+            match target {
+                Target::Set(_) => runtime.latched = None,
+                Target::Reset if matches!(runtime.reported, Some(Target::Set(_))) => {
+                    runtime.latched = Some(status);
+                    return;
+                }
+                Target::Reset => {}
+            }
+        }
+
+        if runtime.reported == Some(target) {
+            return;
+        }
+
+        match delay {
+            Some(delay) => {
+                let emitter = self.clone();
+                let handle = tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+                    emitter.emit(target, status).await;
+                });
+                runtime.pending = Some((target, handle));
+            }
+            None => {
+                runtime.reported = Some(target);
+                drop(alarms);
+                self.publish(status).await;
+            }
+        }
+    }
+
+    /// Moves a discrete alarm, configured in the `Cache`, to `target`.
+    pub async fn discrete(
+        &self,
+        definition: Option<&AlarmDefinition>,
+        name: &str,
+        severity: AlarmSeverity,
+        target: Target,
+        value: i64,
+    ) {
+        if target == Target::Reset {
+            self.clear(definition, name, Some(severity), value, None)
+                .await;
+            return;
+        }
+
+        let status = Alarm {
+            name: name.to_string(),
+            timestamp: Utc::now(),
+            value,
+            state: AlarmState::Set,
+            severity,
+            ack: AlarmAck::NotAck,
+        };
+        self.transition(definition, target, status.into()).await;
+    }
+
+    /// Resets the alarm if it was reported as set, keeping its ack. Without a
+    /// `severity` the one of the alarm is kept.
+    pub async fn clear(
+        &self,
+        definition: Option<&AlarmDefinition>,
+        name: &str,
+        severity: Option<AlarmSeverity>,
+        value: i64,
+        measurement: Option<f64>,
+    ) {
+        let known = {
+            let alarms = self.runtime.lock().await;
+            alarms
+                .get(name)
+                .filter(|runtime| matches!(runtime.reported, Some(Target::Set(_))))
+                .map(|runtime| {
+                    let severity =
+                        severity.or_else(|| runtime.severity(&self.definitions.severities(name)));
+                    (runtime.ack(), severity)
+                })
+        };
+        let Some((ack, severity)) = known else {
+            self.cancel_pending(name).await;
+            return;
+        };
+
+        let status = Alarm {
+            name: name.to_string(),
+            timestamp: Utc::now(),
+            value,
+            state: AlarmState::Reset,
+            // Same default as `AlarmRuntime::state_event`
+            severity: severity.unwrap_or(AlarmSeverity::Low),
+            ack,
+        };
+        let status = AlarmEvent {
+            measurement,
+            ..status.into()
+        };
+        self.transition(definition, Target::Reset, status).await;
+    }
+
+    /// Cancels a transition waiting on its delay when the condition went away
+    /// before anything was reported.
+    pub async fn cancel_pending(&self, name: &str) {
+        if let Some(runtime) = self.runtime.lock().await.get_mut(name) {
+            if let Some((_, handle)) = runtime.pending.take() {
+                handle.abort();
+            }
+        }
+    }
+
+    /// Publishes the event and then the composite alarms it changed.
+    pub async fn publish(&self, event: AlarmEvent) {
+        let mut queue = VecDeque::from([event]);
+
+        while let Some(mut event) = queue.pop_front() {
+            let mut annunciate = self.apply_state(&mut event).await;
+            // Out of service alarms only keep their condition up to date, so
+            // they come back in the right state
+            if event.isa_state == Some(IsaState::OutOfService) {
+                continue;
+            }
+            let name = event.alarm.name.clone();
+            let set = event.alarm.state == AlarmState::Set;
+            if set && annunciate && !event.suppressed {
+                let raised = self.runtime.flood.raise(&name, &event.alarm.severity).await;
+                if let Some(notice) = raised.flood_start {
+                    self.notify(notice).await;
+                }
+                annunciate = !raised.hold;
+            }
+            // Suppressed alarms are only stored, their changes of state are
+            // recorded by `update_suppression`
+            if annunciate && !event.suppressed {
+                AlarmHandler::send_event(&self.tx_publisher, event.clone()).await;
+            }
+            self.db.insert_alm(event).await;
+
+            queue.extend(self.update_composites(&name).await);
+            self.update_suppression(&name).await;
+            if set {
+                self.check_chattering(&name).await;
+            }
+        }
+    }
+
+    /// Publishes an event without storing it again.
+    pub async fn annunciate(&self, event: AlarmEvent) {
+        AlarmHandler::send_event(&self.tx_publisher, event).await;
+    }
+
+    /// Annunciates the alarm on the escalation routing key, with the raised
+    /// severity, and stores it with its own severity.
+    pub async fn escalate(&self, event: AlarmEvent) {
+        let mut escalated = event.clone();
+        if let Some(severity) = &event.escalated_to {
+            escalated.alarm.severity = severity.clone();
+        }
+        let _ = self.tx_publisher.send(Message::Escalated(escalated)).await;
+        self.db.insert_alm(event).await;
+    }
+
+    /// Publishes a notice on the alarms exchange.
+    pub async fn notify(&self, notice: Notice) {
+        let _ = self.tx_publisher.send(Message::Notice(notice)).await;
+    }
+
+    /// Logs an operator command that was rejected and publishes it as an
+    /// `error` notice.
+    pub async fn reject(&self, command: &str, alarm: &str, message: &str) {
+        eprintln!("{command} of '{alarm}' rejected: {message}");
+        self.notify(Notice::Error {
+            command: command.to_string(),
+            alarm: alarm.to_string(),
+            timestamp: Utc::now(),
+            message: message.to_string(),
+        })
+        .await;
+    }
+
+    /// Publishes and stores an event that only changed the state machine,
+    /// e.g. a shelve.
+    pub async fn record(&self, event: AlarmEvent) {
+        if let Some(runtime) = self.runtime.lock().await.get_mut(&event.alarm.name) {
+            runtime.track_unack(event.alarm.timestamp);
+        }
+        AlarmHandler::send_event(&self.tx_publisher, event.clone()).await;
+        self.db.insert_alm(event).await;
+    }
+
+    /// Moves the state machine of the alarm according to the event. Returns
+    /// whether the event should be annunciated.
+    async fn apply_state(&self, event: &mut AlarmEvent) -> bool {
+        let isa_event = match event.alarm.state {
+            AlarmState::Set => IsaEvent::Activate,
+            AlarmState::Reset => IsaEvent::Clear,
+        };
+
+        let mut alarms = self.runtime.lock().await;
+        let suppressed = self.definitions.is_suppressed(&event.alarm.name, &alarms);
+        let runtime = alarms.entry(event.alarm.name.clone()).or_default();
+        if let Err(e) = runtime.machine.handle(isa_event) {
+            eprintln!("Error on the state of {} - {e}", event.alarm.name);
+        }
+        runtime.last = Some(event.alarm.clone());
+        runtime.suppress(&event.alarm.name, suppressed);
+        runtime.track_unack(event.alarm.timestamp);
+
+        let state = runtime.machine.state();
+        event.isa_state = Some(state);
+        event.suppressed = state == IsaState::Suppressed;
+        event.shelved_until = runtime.shelved_until.filter(|_| state == IsaState::Shelved);
+        !matches!(state, IsaState::Shelved | IsaState::OutOfService)
+    }
+
+    /// Counts the activation of the alarm and reports it if it started
+    /// chattering.
+    async fn check_chattering(&self, name: &str) {
+        let Some(config) = self.definitions.get(name).and_then(|d| d.chattering.as_ref()) else {
+            return;
+        };
+
+        let (toggles, shelved) = {
+            let mut alarms = self.runtime.lock().await;
+            let runtime = alarms.entry(name.to_string()).or_default();
+            if !runtime.chatter.push(config, Instant::now()) {
+                return;
+            }
+
+            let shelved = match config.action {
+                ChatterAction::Shelve => {
+                    let until = Utc::now()
+                        + chrono::Duration::from_std(config.duration()).unwrap_or_default();
+                    match runtime.shelve(name, until, "chattering".to_string()) {
+                        Ok(event) => Some(event),
+                        Err(e) => {
+                            eprintln!("Shelve of chattering '{name}' rejected: {e}");
+                            None
+                        }
+                    }
+                }
+                // The delay is applied to the next activations
+                ChatterAction::Delay | ChatterAction::None => None,
+            };
+            (runtime.chatter.toggles(), shelved)
+        };
+
+        self.notify(Notice::Chattering {
+            alarm: name.to_string(),
+            timestamp: Utc::now(),
+            toggles,
+            action: config.action,
+        })
+        .await;
+        if let Some(event) = shelved {
+            self.record(event).await;
+        }
+    }
+
+    /// Re-evaluates the suppression of the alarms with a rule on `source`,
+    /// a trigger or an alarm, and records the ones that changed.
+    pub async fn update_suppression(&self, source: &str) {
+        let suppression = self.definitions.suppression();
+        let rules: Vec<_> = suppression.rules_on(source).collect();
+        if rules.is_empty() {
+            return;
+        }
+
+        let mut events = Vec::new();
+        {
+            let mut alarms = self.runtime.lock().await;
+            for name in self.definitions.names() {
+                if !rules.iter().any(|r| r.applies_to(&name)) {
+                    continue;
+                }
+                let suppressed = self.definitions.is_suppressed(&name, &alarms);
+                let runtime = alarms.entry(name.clone()).or_default();
+                events.extend(runtime.suppress(&name, suppressed));
+            }
+        }
+
+        for event in events {
+            self.record(event).await;
+        }
+    }
+
+    /// Re-evaluates the composites `member` belongs to and returns the
+    /// events of the ones that changed.
+    async fn update_composites(&self, member: &str) -> Vec<AlarmEvent> {
+        let mut changed: Vec<(String, usize, bool, AlarmSeverity)> = Vec::new();
+
+        {
+            let mut alarms = self.runtime.lock().await;
+            for definition in self.definitions.composites_of(member) {
+                let Some(composite) = &definition.composite else {
+                    continue;
+                };
+                let active = composite
+                    .members
+                    .iter()
+                    .filter(|m| {
+                        matches!(
+                            alarms.get(m.as_str()).and_then(|r| r.reported),
+                            Some(Target::Set(_))
+                        )
+                    })
+                    .count();
+                let is_active = composite.is_active(active);
+                let target = if is_active {
+                    Target::Set(None)
+                } else {
+                    Target::Reset
+                };
+
+                let runtime = alarms.entry(definition.name.clone()).or_default();
+                if runtime.reported.unwrap_or(Target::Reset) == target {
+                    continue;
+                }
+                runtime.reported = Some(target);
+                changed.push((
+                    definition.name.clone(),
+                    active,
+                    is_active,
+                    composite.severity.clone(),
+                ));
+            }
+        }
+
+        let mut events = Vec::new();
+        for (name, active, is_active, severity) in changed {
+            let ack = if is_active {
+                AlarmAck::NotAck
+            } else {
+                match self.db.get_latest_alm(name.clone()).await {
+                    Some(alm) => alm.ack,
+                    None => AlarmAck::NotAck,
+                }
+            };
+            let status = Alarm {
+                name,
+                timestamp: Utc::now(),
+                value: active as i64,
+                state: if is_active {
+                    AlarmState::Set
+                } else {
+                    AlarmState::Reset
+                },
+                severity,
+                ack,
+            };
+            events.push(status.into());
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::alarm::Suppression;
+    use crate::config::DBConfig;
+    use std::time::Duration;
+    use tokio::time::sleep;
+
+    /// Emitter for the alarms of `config`. Nothing listens on the DB url, so
+    /// the events are only read from the returned channel.
+    pub(crate) fn emitter(config: &str) -> (Emitter, mpsc::Receiver<Message>) {
+        let (tx_publisher, rx) = mpsc::channel(32);
+        let db = DB::new(DBConfig {
+            url: "http://127.0.0.1:1".to_string(),
+            table: "alarms".to_string(),
+        });
+        let emitter = Emitter {
+            tx_publisher,
+            db,
+            runtime: RuntimeStore::new(),
+            definitions: Definitions::from_yaml(config).unwrap(),
+        };
+        (emitter, rx)
+    }
+
+    pub(crate) fn event(name: &str, state: AlarmState) -> AlarmEvent {
+        Alarm {
+            name: name.to_string(),
+            timestamp: Utc::now(),
+            value: 1,
+            state,
+            severity: AlarmSeverity::High,
+            ack: AlarmAck::NotAck,
+        }
+        .into()
+    }
+
+    /// Alarm state of the next event published, if any.
+    pub(crate) fn published(rx: &mut mpsc::Receiver<Message>) -> Option<AlarmState> {
+        match rx.try_recv() {
+            Ok(Message::Alarm(event)) => Some(event.alarm.state),
+            _ => None,
+        }
+    }
+
+    const CONFIG: &str = r#"
+        sub1:
+          alarm1:
+            on_delay: 5
+            off_delay: 2
+    "#;
+
+    #[tokio::test]
+    async fn test_suppressed() {
+        let config = r#"
+            sub1:
+              alarm1: {}
+              alarm2: {}
+        "#;
+        let (mut emitter, mut rx) = emitter(config);
+        let rules = "[{alarms: sub1/alarm2, when: {alarm: sub1/alarm1}}]";
+        emitter
+            .definitions
+            .set_suppression(Suppression::from_yaml(rules).unwrap());
+        let transition = |name: &'static str, target: Target| {
+            let emitter = emitter.clone();
+            async move {
+                let state = match target {
+                    Target::Set(_) => AlarmState::Set,
+                    Target::Reset => AlarmState::Reset,
+                };
+                emitter.transition(None, target, event(name, state)).await;
+            }
+        };
+        let next = |rx: &mut mpsc::Receiver<Message>| match rx.try_recv() {
+            Ok(Message::Alarm(event)) => Some((event.alarm.name, event.isa_state)),
+            _ => None,
+        };
+
+        // The change to suppressed is published
+        transition("sub1/alarm1", Target::Set(None)).await;
+        assert_eq!(
+            next(&mut rx),
+            Some(("sub1/alarm1".to_string(), Some(IsaState::Unack)))
+        );
+        assert_eq!(
+            next(&mut rx),
+            Some(("sub1/alarm2".to_string(), Some(IsaState::Suppressed)))
+        );
+
+        // But not the alarm while suppressed
+        transition("sub1/alarm2", Target::Set(None)).await;
+        assert_eq!(next(&mut rx), None);
+
+        transition("sub1/alarm1", Target::Reset).await;
+        assert_eq!(
+            next(&mut rx),
+            Some(("sub1/alarm1".to_string(), Some(IsaState::RtnUnack)))
+        );
+        assert_eq!(
+            next(&mut rx),
+            Some(("sub1/alarm2".to_string(), Some(IsaState::Unack)))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_suppressed_stale() {
+        let config = r#"
+            sub1:
+              alarm1:
+                stale: {timeout: 10, severity: 0}
+              alarm2: {}
+        "#;
+        let (mut emitter, mut rx) = emitter(config);
+        let rules = "[{alarms: '*/stale', when: {alarm: sub1/alarm2}}]";
+        emitter
+            .definitions
+            .set_suppression(Suppression::from_yaml(rules).unwrap());
+
+        let set = event("sub1/alarm2", AlarmState::Set);
+        emitter.transition(None, Target::Set(None), set).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Set));
+        let Ok(Message::Alarm(suppressed)) = rx.try_recv() else {
+            panic!("stale alarm not suppressed");
+        };
+        assert_eq!(suppressed.alarm.name, "sub1/alarm1/stale");
+        assert_eq!(suppressed.isa_state, Some(IsaState::Suppressed));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_expired() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let definition = emitter.definitions.get("sub1/alarm1").cloned();
+        let set = || event("sub1/alarm1", AlarmState::Set);
+
+        emitter.transition(definition.as_ref(), Target::Set(None), set()).await;
+        sleep(Duration::from_secs(3)).await;
+        // The condition holding doesn't restart the delay
+        emitter.transition(definition.as_ref(), Target::Set(None), set()).await;
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(published(&mut rx), None);
+
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Set));
+        let alarms = emitter.runtime.lock().await;
+        let runtime = &alarms["sub1/alarm1"];
+        assert_eq!(runtime.reported, Some(Target::Set(None)));
+        assert!(runtime.pending.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_cancelled() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let definition = emitter.definitions.get("sub1/alarm1").cloned();
+
+        let set = event("sub1/alarm1", AlarmState::Set);
+        emitter.transition(definition.as_ref(), Target::Set(None), set).await;
+        sleep(Duration::from_secs(2)).await;
+        emitter.cancel_pending("sub1/alarm1").await;
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(published(&mut rx), None);
+        assert_eq!(emitter.runtime.lock().await["sub1/alarm1"].reported, None);
+
+        // The condition coming back before the off delay keeps the alarm set
+        emitter.runtime.lock().await.get_mut("sub1/alarm1").unwrap().reported =
+            Some(Target::Set(None));
+        let reset = event("sub1/alarm1", AlarmState::Reset);
+        emitter.transition(definition.as_ref(), Target::Reset, reset).await;
+        sleep(Duration::from_secs(1)).await;
+        let set = event("sub1/alarm1", AlarmState::Set);
+        emitter.transition(definition.as_ref(), Target::Set(None), set).await;
+        sleep(Duration::from_secs(10)).await;
+        assert_eq!(published(&mut rx), None);
+        assert_eq!(
+            emitter.runtime.lock().await["sub1/alarm1"].reported,
+            Some(Target::Set(None))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delay_rearmed() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let definition = emitter.definitions.get("sub1/alarm1").cloned();
+        let set = || event("sub1/alarm1", AlarmState::Set);
+
+        emitter.transition(definition.as_ref(), Target::Set(None), set()).await;
+        sleep(Duration::from_secs(3)).await;
+        emitter.cancel_pending("sub1/alarm1").await;
+        sleep(Duration::from_secs(1)).await;
+
+        // The delay starts over
+        emitter.transition(definition.as_ref(), Target::Set(None), set()).await;
+        sleep(Duration::from_secs(4)).await;
+        assert_eq!(published(&mut rx), None);
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Set));
+
+        let reset = event("sub1/alarm1", AlarmState::Reset);
+        emitter.transition(definition.as_ref(), Target::Reset, reset).await;
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(published(&mut rx), None);
+        sleep(Duration::from_secs(2)).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Reset));
+    }
+
+    #[tokio::test]
+    async fn test_clear() {
+        let config = r#"
+            sub1:
+              alarm1: {}
+              alarm2:
+                analog:
+                  hi: {value: 10, severity: 1}
+        "#;
+        let (emitter, mut rx) = emitter(config);
+        let next = |rx: &mut mpsc::Receiver<Message>| match rx.try_recv() {
+            Ok(Message::Alarm(event)) => event,
+            other => panic!("unexpected {other:?}"),
+        };
+
+        // Nothing to clear
+        emitter.clear(None, "sub1/alarm1", None, 0, None).await;
+        assert!(rx.try_recv().is_err());
+
+        // The ack and severity come from the alarm, not from the DB
+        let mut set = event("sub1/alarm1", AlarmState::Set);
+        set.alarm.severity = AlarmSeverity::Medium;
+        emitter.transition(None, Target::Set(None), set).await;
+        next(&mut rx);
+        {
+            let mut alarms = emitter.runtime.lock().await;
+            crate::alarm::ack::apply_ack(alarms.get_mut("sub1/alarm1").unwrap());
+        }
+        emitter.clear(None, "sub1/alarm1", None, 0, Some(0.5)).await;
+        let reset = next(&mut rx);
+        assert_eq!(reset.alarm.state, AlarmState::Reset);
+        assert_eq!(reset.alarm.ack, AlarmAck::Ack);
+        assert_eq!(reset.alarm.severity, AlarmSeverity::Medium);
+        assert_eq!(reset.measurement, Some(0.5));
+
+        // A restored alarm gets the severity of its definition
+        emitter
+            .runtime
+            .restore_states(vec![crate::db::StoredState {
+                name: "sub1/alarm2".to_string(),
+                machine: crate::alarm::StateMachine::new(IsaState::Unack, true),
+                shelved_until: None,
+                severity: None,
+                unack_since: None,
+                escalated: false,
+                value: Some(12.0),
+            }])
+            .await;
+        emitter.clear(None, "sub1/alarm2", None, 5, None).await;
+        let reset = next(&mut rx);
+        assert_eq!(reset.alarm.ack, AlarmAck::NotAck);
+        assert_eq!(reset.alarm.severity, AlarmSeverity::Medium);
+        assert_eq!(reset.isa_state, Some(IsaState::RtnUnack));
+    }
+
+    #[tokio::test]
+    async fn test_discrete() {
+        let config = r#"
+            sub1:
+              alarm2:
+                latching: true
+        "#;
+        let (emitter, mut rx) = emitter(config);
+        let discrete = |name: &'static str, target: Target| {
+            let emitter = emitter.clone();
+            async move {
+                let definition = emitter.definitions.get(name).cloned();
+                let value = if target == Target::Reset { 0 } else { 1 };
+                let severity = AlarmSeverity::High;
+                emitter
+                    .discrete(definition.as_ref(), name, severity, target, value)
+                    .await;
+            }
+        };
+        let reported = |name: &'static str| {
+            let runtime = emitter.runtime.clone();
+            async move { runtime.lock().await[name].reported }
+        };
+
+        // Only configured in the cache
+        discrete("sub1/alarm1", Target::Set(None)).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Set));
+        discrete("sub1/alarm1", Target::Reset).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Reset));
+        assert_eq!(reported("sub1/alarm1").await, Some(Target::Reset));
+        discrete("sub1/alarm1", Target::Reset).await;
+        assert_eq!(published(&mut rx), None);
+
+        // The reset of a latching alarm waits for the operator
+        discrete("sub1/alarm2", Target::Set(None)).await;
+        assert_eq!(published(&mut rx), Some(AlarmState::Set));
+        discrete("sub1/alarm2", Target::Reset).await;
+        assert_eq!(published(&mut rx), None);
+        assert_eq!(reported("sub1/alarm2").await, Some(Target::Set(None)));
+        let alarms = emitter.runtime.lock().await;
+        assert!(alarms["sub1/alarm2"].latched.is_some());
+    }
+}
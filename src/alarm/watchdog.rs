@@ -0,0 +1,228 @@
+use crate::alarm::{
+    Alarm, AlarmAck, AlarmEvent, AlarmSeverity, AlarmState, Definitions, Emitter, Message,
+    RuntimeStore,
+};
+use crate::db::DB;
+use chrono::Utc;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Raises `<alarm>/stale` when no trigger arrived for the alarm in `timeout`
+/// seconds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Stale {
+    pub timeout: f64,
+    pub severity: AlarmSeverity,
+}
+
+impl Stale {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout)
+    }
+
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        let timeout = Duration::try_from_secs_f64(self.timeout).ok();
+        if timeout.filter(|t| !t.is_zero()).is_none() {
+            return Err(format!("invalid stale timeout {} of '{name}'", self.timeout));
+        }
+        Ok(())
+    }
+
+    pub fn alarm_name(name: &str) -> String {
+        format!("{name}/stale")
+    }
+}
+
+/// Periodically checks the time of the last trigger of every alarm with a
+/// `stale` configuration.
+#[derive(Debug)]
+pub struct Watchdog {
+    emitter: Emitter,
+}
+
+impl Watchdog {
+    pub fn new(
+        tx_publisher: mpsc::Sender<Message>,
+        db: DB,
+        definitions: Definitions,
+        runtime: RuntimeStore,
+    ) -> Self {
+        Self {
+            emitter: Emitter {
+                tx_publisher,
+                db,
+                runtime,
+                definitions,
+            },
+        }
+    }
+
+    pub async fn run(&self) {
+        // Alarms that never got a trigger are stale once the timeout passed
+        // since the server started.
+        let started = Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            interval.tick().await;
+            self.check(started).await;
+        }
+    }
+
+    async fn check(&self, started: Instant) {
+        let now = Instant::now();
+        let mut changed = Vec::new();
+
+        {
+            let mut alarms = self.emitter.runtime.lock().await;
+            for definition in self.emitter.definitions.iter() {
+                let Some(stale) = &definition.stale else {
+                    continue;
+                };
+                let runtime = alarms.entry(definition.name.clone()).or_default();
+                let last_seen = runtime.last_seen.unwrap_or(started);
+                let is_stale = now.duration_since(last_seen) > stale.timeout();
+
+                if is_stale != runtime.stale {
+                    runtime.stale = is_stale;
+                    changed.push((
+                        Stale::alarm_name(&definition.name),
+                        is_stale,
+                        stale.severity.clone(),
+                        runtime.last_value,
+                    ));
+                }
+            }
+        }
+
+        for (name, is_stale, severity, value) in changed {
+            let ack = if is_stale {
+                AlarmAck::NotAck
+            } else {
+                match self.emitter.db.get_latest_alm(name.clone()).await {
+                    Some(alm) => alm.ack,
+                    None => {
+                        eprintln!("Error reading the last status of {name}");
+                        AlarmAck::NotAck
+                    }
+                }
+            };
+
+            let status = Alarm {
+                name,
+                timestamp: Utc::now(),
+                value: value.unwrap_or_default().round() as i64,
+                state: if is_stale {
+                    AlarmState::Set
+                } else {
+                    AlarmState::Reset
+                },
+                severity,
+                ack,
+            };
+            let status = AlarmEvent {
+                measurement: value,
+                ..status.into()
+            };
+            self.emitter.publish(status).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::transition::tests::emitter;
+    use tokio::time::sleep;
+
+    const CONFIG: &str = r#"
+        sub1:
+          alarm1:
+            stale:
+              timeout: 10
+              severity: 0
+    "#;
+
+    fn published(rx: &mut mpsc::Receiver<Message>) -> Option<AlarmEvent> {
+        match rx.try_recv() {
+            Ok(Message::Alarm(event)) => Some(event),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let stale = |timeout: f64| Stale {
+            timeout,
+            severity: AlarmSeverity::Low,
+        };
+        assert!(stale(0.5).validate("sub1/alarm1").is_ok());
+        for timeout in [0.0, -10.0, f64::NAN, f64::INFINITY, 1e300] {
+            assert!(stale(timeout).validate("sub1/alarm1").is_err());
+        }
+        let config = "sub1:\n  alarm1:\n    stale: {timeout: .inf, severity: 0}";
+        assert!(Definitions::from_yaml(config).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_never_seen() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let watchdog = Watchdog { emitter };
+        let started = Instant::now();
+
+        sleep(Duration::from_secs(5)).await;
+        watchdog.check(started).await;
+        assert!(published(&mut rx).is_none());
+
+        sleep(Duration::from_secs(6)).await;
+        watchdog.check(started).await;
+        let event = published(&mut rx).unwrap();
+        assert_eq!(event.alarm.name, "sub1/alarm1/stale");
+        assert_eq!(event.alarm.state, AlarmState::Set);
+        assert_eq!(event.measurement, None);
+        assert!(watchdog.emitter.runtime.lock().await["sub1/alarm1"].stale);
+
+        // Only the change is published
+        watchdog.check(started).await;
+        assert!(published(&mut rx).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_and_back() {
+        let (emitter, mut rx) = emitter(CONFIG);
+        let watchdog = Watchdog { emitter };
+        let started = Instant::now();
+        let seen = |value: f64| {
+            let runtime = watchdog.emitter.runtime.clone();
+            async move {
+                let mut alarms = runtime.lock().await;
+                let runtime = alarms.entry("sub1/alarm1".to_string()).or_default();
+                runtime.last_seen = Some(Instant::now());
+                runtime.last_value = Some(value);
+            }
+        };
+
+        sleep(Duration::from_secs(8)).await;
+        seen(70000.25).await;
+        sleep(Duration::from_secs(8)).await;
+        watchdog.check(started).await;
+        assert!(published(&mut rx).is_none());
+
+        sleep(Duration::from_secs(3)).await;
+        watchdog.check(started).await;
+        let event = published(&mut rx).unwrap();
+        assert_eq!(event.alarm.state, AlarmState::Set);
+        assert_eq!(event.alarm.value, 70000);
+        assert_eq!(event.measurement, Some(70000.25));
+
+        seen(12.5).await;
+        watchdog.check(started).await;
+        let event = published(&mut rx).unwrap();
+        assert_eq!(event.alarm.name, "sub1/alarm1/stale");
+        assert_eq!(event.alarm.state, AlarmState::Reset);
+        assert_eq!(event.measurement, Some(12.5));
+        assert!(!watchdog.emitter.runtime.lock().await["sub1/alarm1"].stale);
+    }
+}
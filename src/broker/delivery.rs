@@ -0,0 +1,323 @@
+use crate::broker::spool::{Outgoing, Spool};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Time the broker has to confirm a message before it's published again.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Publisher confirm, return of an unroutable message, or the loss of the
+/// channel, sent by the broker.
+#[derive(Debug)]
+pub enum Confirm {
+    Ack {
+        tag: u64,
+        multiple: bool,
+    },
+    Nack {
+        tag: u64,
+    },
+    Returned {
+        routing_key: String,
+        payload: String,
+        reply: String,
+    },
+    Closed,
+}
+
+/// Message published and waiting for its confirm.
+#[derive(Debug)]
+pub struct Pending {
+    pub tag: u64,
+    pub sent: Instant,
+    pub message: Outgoing,
+}
+
+/// Removes the confirmed messages from `pending`. Rejected messages and the
+/// loss of the channel are errors: the messages still pending have to be
+/// published again. Returned messages are dropped, no queue is bound to
+/// take them.
+pub fn confirm(pending: &mut VecDeque<Pending>, confirm: Confirm) -> Result<(), String> {
+    match confirm {
+        Confirm::Ack {
+            tag,
+            multiple: true,
+        } => {
+            while pending.front().is_some_and(|p| p.tag <= tag) {
+                pending.pop_front();
+            }
+        }
+        Confirm::Ack { tag, .. } => pending.retain(|p| p.tag != tag),
+        Confirm::Nack { tag } => return Err(format!("message {tag} rejected")),
+        // The broker acks the returned message right after, publishing it
+        // again would only get it returned again
+        Confirm::Returned {
+            routing_key,
+            payload,
+            reply,
+        } => {
+            eprintln!(
+                "Dropping the message to '{routing_key}', no queue is bound for it - {reply}"
+            );
+            let returned = pending
+                .iter()
+                .position(|p| p.message.routing_key == routing_key && p.message.payload == payload);
+            if let Some(returned) = returned {
+                pending.remove(returned);
+            }
+        }
+        Confirm::Closed => return Err("channel closed".to_string()),
+    }
+    Ok(())
+}
+
+/// Whether the oldest pending message wasn't confirmed in time.
+pub fn is_late(pending: &VecDeque<Pending>) -> bool {
+    pending
+        .front()
+        .is_some_and(|p| p.sent.elapsed() > CONFIRM_TIMEOUT)
+}
+
+/// Messages not confirmed yet, in the order they were received.
+pub fn unconfirmed<'a>(
+    pending: &'a VecDeque<Pending>,
+    buffer: &'a VecDeque<Outgoing>,
+) -> impl Iterator<Item = &'a Outgoing> {
+    pending.iter().map(|p| &p.message).chain(buffer)
+}
+
+/// Rewrites the spool with the messages not confirmed yet, removing it once
+/// everything is. Returns whether the spool still holds messages.
+pub fn respool(spool: &Spool, pending: &VecDeque<Pending>, buffer: &VecDeque<Outgoing>) -> bool {
+    let result = if pending.is_empty() && buffer.is_empty() {
+        spool.clear()
+    } else {
+        spool.replace(unconfirmed(pending, buffer))
+    };
+    match result {
+        Ok(()) => !pending.is_empty() || !buffer.is_empty(),
+        // The previous content is still there
+        Err(e) => {
+            eprintln!("Couldn't rewrite the spool - {e}");
+            true
+        }
+    }
+}
+
+/// Moves the messages not confirmed to the spool, or keeps them in the
+/// buffer if it can't be written. Returns whether they were spooled.
+pub fn stash(
+    spool: &Spool,
+    pending: &mut VecDeque<Pending>,
+    buffer: &mut VecDeque<Outgoing>,
+) -> bool {
+    if pending.is_empty() && buffer.is_empty() {
+        return false;
+    }
+
+    match spool.replace(unconfirmed(pending, buffer)) {
+        Ok(()) => {
+            pending.clear();
+            buffer.clear();
+            true
+        }
+        Err(e) => {
+            eprintln!("Couldn't spool the messages, keeping them in memory - {e}");
+            let mut kept: VecDeque<Outgoing> = pending.drain(..).map(|p| p.message).collect();
+            kept.append(buffer);
+            *buffer = kept;
+            false
+        }
+    }
+}
+
+/// Spools a message received while the broker is unavailable. Returns
+/// whether it was spooled.
+pub fn spool_message(spool: &Spool, buffer: &mut VecDeque<Outgoing>, message: Outgoing) -> bool {
+    // Once something is kept in memory the rest follows it, to keep the
+    // order
+    if buffer.is_empty() {
+        match spool.append([&message]) {
+            Ok(()) => return true,
+            Err(e) => eprintln!("Couldn't spool the message, keeping it in memory - {e}"),
+        }
+    }
+    buffer.push_back(message);
+    false
+}
+
+/// Puts the spooled messages back in front of the buffered ones, to be
+/// published again.
+pub fn replay(spool: &Spool, buffer: &mut VecDeque<Outgoing>) {
+    let mut replay = spool.load();
+    replay.append(buffer);
+    *buffer = replay;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::spool::tests::message;
+
+    fn pending(tags: &[u64]) -> VecDeque<Pending> {
+        tags.iter()
+            .map(|tag| Pending {
+                tag: *tag,
+                sent: Instant::now(),
+                message: message(&format!("a.{tag}")),
+            })
+            .collect()
+    }
+
+    fn tags(pending: &VecDeque<Pending>) -> Vec<u64> {
+        pending.iter().map(|p| p.tag).collect()
+    }
+
+    fn keys<'a>(messages: impl IntoIterator<Item = &'a Outgoing>) -> Vec<&'a str> {
+        messages
+            .into_iter()
+            .map(|m| m.routing_key.as_str())
+            .collect()
+    }
+
+    fn spool(name: &str) -> Spool {
+        let path =
+            std::env::temp_dir().join(format!("alarm_spool_{name}_{}.jsonl", std::process::id()));
+        let spool = Spool::new(path);
+        spool.clear().unwrap();
+        spool
+    }
+
+    #[test]
+    fn test_confirm() {
+        let mut queue = pending(&[1, 2, 3, 4, 5]);
+
+        let single = Confirm::Ack {
+            tag: 3,
+            multiple: false,
+        };
+        confirm(&mut queue, single).unwrap();
+        assert_eq!(tags(&queue), [1, 2, 4, 5]);
+
+        let multiple = Confirm::Ack {
+            tag: 4,
+            multiple: true,
+        };
+        confirm(&mut queue, multiple).unwrap();
+        assert_eq!(tags(&queue), [5]);
+
+        // Confirms of unknown tags change nothing
+        let unknown = Confirm::Ack {
+            tag: 2,
+            multiple: false,
+        };
+        confirm(&mut queue, unknown).unwrap();
+        assert_eq!(tags(&queue), [5]);
+
+        assert!(confirm(&mut queue, Confirm::Nack { tag: 5 }).is_err());
+        assert!(confirm(&mut queue, Confirm::Closed).is_err());
+        // Failures leave the messages to publish again
+        assert_eq!(tags(&queue), [5]);
+    }
+
+    #[test]
+    fn test_returned() {
+        let mut queue = pending(&[1, 2, 3]);
+        let returned = message("a.2");
+        let returned = Confirm::Returned {
+            routing_key: returned.routing_key,
+            payload: returned.payload,
+            reply: "NO_ROUTE".to_string(),
+        };
+        confirm(&mut queue, returned).unwrap();
+        assert_eq!(tags(&queue), [1, 3]);
+
+        // The ack the broker sends for it changes nothing
+        let ack = Confirm::Ack {
+            tag: 2,
+            multiple: false,
+        };
+        confirm(&mut queue, ack).unwrap();
+        assert_eq!(tags(&queue), [1, 3]);
+
+        let ack = Confirm::Ack {
+            tag: 3,
+            multiple: true,
+        };
+        confirm(&mut queue, ack).unwrap();
+        assert!(queue.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_late() {
+        let mut queue = pending(&[1]);
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        queue.extend(pending(&[2]));
+        assert!(!is_late(&queue));
+
+        tokio::time::sleep(Duration::from_secs(15)).await;
+        assert!(is_late(&queue));
+
+        // Only the oldest message counts
+        queue.pop_front();
+        assert!(!is_late(&queue));
+    }
+
+    #[test]
+    fn test_replay_order() {
+        let spool = spool("replay");
+        let mut queue = pending(&[1, 2]);
+        let mut buffer = VecDeque::from([message("a.3")]);
+
+        assert!(stash(&spool, &mut queue, &mut buffer));
+        assert!(queue.is_empty() && buffer.is_empty());
+        assert!(spool_message(&spool, &mut buffer, message("a.4")));
+
+        replay(&spool, &mut buffer);
+        assert_eq!(keys(&buffer), ["a.1", "a.2", "a.3", "a.4"]);
+        spool.clear().unwrap();
+    }
+
+    #[test]
+    fn test_replay_unwritable() {
+        let spool = Spool::new(std::env::temp_dir().join("missing_dir/alarm_spool.jsonl"));
+        let mut queue = pending(&[1, 2]);
+        let mut buffer = VecDeque::from([message("a.3")]);
+
+        // Everything stays in memory, in order
+        assert!(!stash(&spool, &mut queue, &mut buffer));
+        assert!(!spool_message(&spool, &mut buffer, message("a.4")));
+        assert!(queue.is_empty());
+
+        replay(&spool, &mut buffer);
+        assert_eq!(keys(&buffer), ["a.1", "a.2", "a.3", "a.4"]);
+    }
+
+    #[test]
+    fn test_respool() {
+        let spool = spool("respool");
+        let mut queue = pending(&[1, 2, 3]);
+        let mut buffer = VecDeque::from([message("a.4")]);
+        spool.replace(unconfirmed(&queue, &buffer)).unwrap();
+
+        let ack = Confirm::Ack {
+            tag: 2,
+            multiple: true,
+        };
+        confirm(&mut queue, ack).unwrap();
+        assert!(respool(&spool, &queue, &buffer));
+        assert_eq!(keys(&spool.load()), ["a.3", "a.4"]);
+
+        buffer.clear();
+        confirm(
+            &mut queue,
+            Confirm::Ack {
+                tag: 3,
+                multiple: false,
+            },
+        )
+        .unwrap();
+        assert!(!respool(&spool, &queue, &buffer));
+        assert!(spool.load().is_empty());
+    }
+}
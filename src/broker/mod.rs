@@ -1,20 +1,44 @@
-use crate::config::BrokerConfig;
+use crate::config::{BrokerConfig, ExchangeConfig, TlsConfig};
 use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
+    callbacks::{ChannelCallback, DefaultConnectionCallback},
+    channel::Channel,
     connection::{Connection, OpenConnectionArguments},
+    security::SecurityCredentials,
+    tls::TlsAdaptor,
 };
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
 
+pub mod delivery;
 pub mod reader;
+pub mod spool;
 pub mod writer;
 pub use crate::broker::reader::Reader;
+pub use crate::broker::spool::Spool;
 pub use crate::broker::writer::Writer;
 
+pub type BrokerError = Box<dyn std::error::Error + Send + Sync>;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Connection to RabbitMQ shared by the reader and the writer. Whoever
+/// notices the connection is gone reconnects it for both.
+#[derive(Clone)]
 pub struct Broker {
     host: String,
     port: u16,
     username: String,
     password: String,
-    connection: Option<Connection>,
+    vhost: String,
+    triggers: ExchangeConfig,
+    acks: ExchangeConfig,
+    alarms: ExchangeConfig,
+    spool: String,
+    tls: Option<TlsConfig>,
+    connection: Arc<Mutex<Option<Connection>>>,
 }
 
 impl Broker {
@@ -24,40 +48,93 @@ impl Broker {
             port: config.port,
             username: config.username,
             password: config.password,
-            connection: None,
+            vhost: config.vhost,
+            triggers: config.triggers,
+            acks: config.acks,
+            alarms: config.alarms,
+            spool: config.spool,
+            tls: config.tls,
+            connection: Arc::default(),
+        }
+    }
+
+    /// Connects once, without retrying.
+    pub async fn connect(&self) -> Result<(), BrokerError> {
+        let mut connection = self.connection.lock().await;
+        *connection = Some(self.open().await?);
+        Ok(())
+    }
+
+    /// Connects again, waiting longer after each failed attempt. Returns
+    /// right away if the connection is open.
+    pub async fn reconnect(&self) {
+        let mut connection = self.connection.lock().await;
+        let mut backoff = MIN_BACKOFF;
+
+        while !connection.as_ref().is_some_and(|c| c.is_open()) {
+            match self.open().await {
+                Ok(c) => {
+                    println!("Connected to rabbitMQ");
+                    *connection = Some(c);
+                }
+                Err(e) => {
+                    eprintln!("Couldn't connect to rabbitMQ, retrying in {backoff:?} - {e}");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
         }
     }
 
-    pub async fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        self.connection = Some(
-            Connection::open(&OpenConnectionArguments::new(
-                &self.host,
-                self.port,
-                &self.username,
-                &self.password,
-            ))
-            .await?,
-        );
-
-        self.connection
-            .as_ref()
-            .unwrap()
+    async fn open(&self) -> Result<Connection, BrokerError> {
+        let mut args =
+            OpenConnectionArguments::new(&self.host, self.port, &self.username, &self.password);
+        args.virtual_host(&self.vhost);
+        if let Some(tls) = &self.tls {
+            args.tls_adaptor(self.tls_adaptor(tls)?);
+            if tls.external_auth {
+                args.credentials(SecurityCredentials::new_external());
+            }
+        }
+
+        let connection = Connection::open(&args).await?;
+        connection
             .register_callback(DefaultConnectionCallback)
             .await?;
+        Ok(connection)
+    }
 
-        Ok(())
+    fn tls_adaptor(&self, tls: &TlsConfig) -> Result<TlsAdaptor, BrokerError> {
+        let domain = tls.server_name.clone().unwrap_or_else(|| self.host.clone());
+        let ca = tls.ca.as_deref().map(Path::new);
+
+        let adaptor = match (&tls.cert, &tls.key) {
+            (Some(cert), Some(key)) => {
+                TlsAdaptor::with_client_auth(ca, Path::new(cert), Path::new(key), domain)?
+            }
+            _ => TlsAdaptor::without_client_auth(ca, domain)?,
+        };
+        Ok(adaptor)
+    }
+
+    pub async fn open_channel<C>(&self, callback: C) -> Result<Channel, BrokerError>
+    where
+        C: ChannelCallback + Send + 'static,
+    {
+        let connection = self.connection.lock().await;
+        let Some(connection) = connection.as_ref() else {
+            return Err("not connected to rabbitMQ".into());
+        };
+        let channel = connection.open_channel(None).await?;
+        channel.register_callback(callback).await?;
+        Ok(channel)
     }
 
-    pub async fn create_reader(&self) -> Result<Reader, Box<dyn std::error::Error>> {
-        // open a channel on the connection
-        let channel = self.connection.as_ref().unwrap().open_channel(None).await?;
-        channel.register_callback(DefaultChannelCallback).await?;
-        Ok(Reader::new(channel))
+    pub fn create_reader(&self) -> Reader {
+        Reader::new(self.clone(), self.triggers.clone(), self.acks.clone())
     }
 
-    pub async fn create_writer(&self) -> Result<Writer, Box<dyn std::error::Error>> {
-        let channel = self.connection.as_ref().unwrap().open_channel(None).await?;
-        channel.register_callback(DefaultChannelCallback).await?;
-        Ok(Writer::new(channel))
+    pub fn create_writer(&self) -> Writer {
+        Writer::new(self.clone(), self.alarms.clone(), Spool::new(&self.spool))
     }
 }
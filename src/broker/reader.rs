@@ -1,147 +1,233 @@
+use crate::broker::{Broker, BrokerError};
+use crate::config::ExchangeConfig;
+use amqprs::callbacks::DefaultChannelCallback;
 use amqprs::channel::{
     BasicAckArguments, BasicConsumeArguments, Channel, ExchangeDeclareArguments,
     QueueBindArguments, QueueDeclareArguments,
 };
+use amqprs::Deliver;
 use async_channel;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
-const ALM_EXCHANGE: &str = "alm_trg_exchange";
-const ACK_EXCHANGE: &str = "ack_exchange";
+/// Binding key of the acks if not configured.
+const ACK_KEY: &str = "ack";
+/// Operator commands received on the ack exchange next to the acks.
+const COMMAND_KEYS: [&str; 6] = [
+    "chattering_report",
+    "reset",
+    "shelve",
+    "unshelve",
+    "remove_from_service",
+    "return_to_service",
+];
 
 pub struct Reader {
-    channel: Channel,
-    alm_exchange: String,
-    ack_exchange: String,
+    broker: Broker,
+    channel: Option<Channel>,
+    triggers: ExchangeConfig,
+    acks: ExchangeConfig,
     queue_name: String,
     ack_queue: String,
     ack_tx: Option<mpsc::Sender<String>>,
+    cmd_tx: Option<mpsc::Sender<(String, String)>>,
     alm_tx: Option<async_channel::Sender<String>>,
 }
 
 impl Reader {
-    pub fn new(channel: Channel) -> Self {
+    pub fn new(broker: Broker, triggers: ExchangeConfig, acks: ExchangeConfig) -> Self {
         Self {
-            channel: channel,
-            alm_exchange: String::from(ALM_EXCHANGE),
-            ack_exchange: String::from(ACK_EXCHANGE),
+            broker,
+            channel: None,
+            triggers,
+            acks,
             queue_name: String::new(),
             ack_queue: String::new(),
             ack_tx: None,
+            cmd_tx: None,
             alm_tx: None,
         }
     }
 
-    pub async fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let x_args = ExchangeDeclareArguments::new(&self.alm_exchange, "direct")
+    /// Opens a channel and declares and binds the exchanges and queues.
+    pub async fn connect(&mut self) -> Result<(), BrokerError> {
+        let channel = self.broker.open_channel(DefaultChannelCallback).await?;
+
+        let x_type = self.triggers.kind.unwrap_or_default().as_str();
+        let x_args = ExchangeDeclareArguments::new(&self.triggers.name, x_type)
             .durable(true)
             .finish();
-        self.channel.exchange_declare(x_args).await?;
+        channel.exchange_declare(x_args).await?;
 
+        // Deleted with the connection, a reconnect declares a new one
         let q_args = QueueDeclareArguments::new("")
             .durable(false)
+            .exclusive(true)
             .finish();
-        (self.queue_name, _, _) = self.channel.queue_declare(q_args).await?.unwrap();
+        (self.queue_name, _, _) = channel
+            .queue_declare(q_args)
+            .await?
+            .ok_or("no queue declared")?;
 
-        self.channel
+        channel
             .queue_bind(QueueBindArguments::new(
                 &self.queue_name,
-                &self.alm_exchange,
-                "",
+                &self.triggers.name,
+                self.triggers.routing_key.as_deref().unwrap_or_default(),
             ))
-            .await
-            .unwrap();
+            .await?;
 
-        self.bind_ack().await;
+        self.bind_ack(&channel).await?;
+        self.channel = Some(channel);
 
         Ok(())
     }
 
-    pub async fn receive(&self) {
+    /// Consumes the triggers and acks, reconnecting whenever the broker goes
+    /// away.
+    pub async fn receive(&mut self) {
+        loop {
+            if let Err(e) = self.consume().await {
+                eprintln!("Lost the rabbitMQ consumers - {e}");
+            }
+            self.reconnect().await;
+        }
+    }
+
+    async fn reconnect(&mut self) {
+        loop {
+            self.broker.reconnect().await;
+            match self.connect().await {
+                Ok(()) => return,
+                Err(e) => {
+                    eprintln!("Couldn't set the reader up again - {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    async fn consume(&self) -> Result<(), BrokerError> {
         println!("init receive");
+        let channel = self.channel.as_ref().ok_or("reader not connected")?;
+
         let consumer_args = BasicConsumeArguments::default()
             .queue(self.queue_name.clone())
             .finish();
-        let (_ctag, mut rx) = self.channel.basic_consume_rx(consumer_args).await.unwrap();
+        let (_ctag, mut rx) = channel.basic_consume_rx(consumer_args).await?;
 
         let consumer_args = BasicConsumeArguments::default()
             .queue(self.ack_queue.clone())
             .finish();
-        let (_ctag, mut ack_rx) = self.channel.basic_consume_rx(consumer_args).await.unwrap();
+        let (_ctag, mut ack_rx) = channel.basic_consume_rx(consumer_args).await?;
 
         println!("waiting on data");
         loop {
             tokio::select! {
                 Some(msg) = rx.recv() => {
-                    if let Some(payload) = msg.content {
-
-                        let payload = std::str::from_utf8(&payload).unwrap();
-                        if let Err(e) = self.alm_tx.as_ref().unwrap().send(payload.to_string()).await {
-                            eprintln!(
-                                "Error sending value '{payload}' - {e}"
-                            )
+                    let (Some(deliver), Some(payload)) = (msg.deliver, msg.content) else {
+                        continue;
+                    };
+
+                    match std::str::from_utf8(&payload) {
+                        Ok(payload) => {
+                            if let Err(e) = self.alm_tx.as_ref().unwrap().send(payload.to_string()).await {
+                                eprintln!(
+                                    "Error sending value '{payload}' - {e}"
+                                )
+                            }
                         }
-
-                        self.channel
-                            .basic_ack(BasicAckArguments::new(
-                                msg.deliver.unwrap().delivery_tag(),
-                                false,
-                            ))
-                            .await
-                            .unwrap();
+                        Err(e) => eprintln!("Invalid trigger - {e}"),
                     }
+
+                    Self::ack(channel, &deliver).await?;
                 },
                 Some(msg) = ack_rx.recv() => {
-                    if let Some(payload) = msg.content {
-                        let payload = std::str::from_utf8(&payload).unwrap();
-                        if let Err(e) = self.ack_tx.as_ref().unwrap().send(payload.to_string()).await {
-                            eprintln!(
-                                "Error sending ack to '{payload}' - {e}"
-                            )
-                        }
+                    let (Some(deliver), Some(payload)) = (msg.deliver, msg.content) else {
+                        continue;
+                    };
 
-                        self.channel
-                            .basic_ack(BasicAckArguments::new(
-                                msg.deliver.unwrap().delivery_tag(),
-                                false,
-                            ))
-                            .await
-                            .unwrap();
+                    match std::str::from_utf8(&payload) {
+                        Ok(payload) => self.dispatch(deliver.routing_key(), payload).await,
+                        Err(e) => eprintln!("Invalid command - {e}"),
                     }
+
+                    Self::ack(channel, &deliver).await?;
                 }
+                else => return Err("consumers closed".into()),
             }
         }
     }
 
-    async fn bind_ack(&mut self) {
-        let x_type = "direct";
-        let x_args = ExchangeDeclareArguments::new(&self.ack_exchange, x_type)
+    /// Sends an ack or a command to its handler. Anything but a command is
+    /// an ack, its key may match a topic pattern.
+    async fn dispatch(&self, key: &str, payload: &str) {
+        if !COMMAND_KEYS.contains(&key) {
+            if let Err(e) = self.ack_tx.as_ref().unwrap().send(payload.to_string()).await {
+                eprintln!(
+                    "Error sending ack to '{payload}' - {e}"
+                )
+            }
+        } else if let Err(e) = self
+            .cmd_tx
+            .as_ref()
+            .unwrap()
+            .send((key.to_string(), payload.to_string()))
+            .await
+        {
+            eprintln!(
+                "Error sending command '{key}' - {e}"
+            )
+        }
+    }
+
+    async fn ack(channel: &Channel, deliver: &Deliver) -> Result<(), BrokerError> {
+        channel
+            .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
+            .await?;
+        Ok(())
+    }
+
+    async fn bind_ack(&mut self, channel: &Channel) -> Result<(), BrokerError> {
+        let x_type = self.acks.kind.unwrap_or_default().as_str();
+        let x_args = ExchangeDeclareArguments::new(&self.acks.name, x_type)
             .durable(true)
             .finish();
-        self.channel.exchange_declare(x_args).await.unwrap();
+        channel.exchange_declare(x_args).await?;
 
-        println!("conn open: {}", self.channel.is_connection_open());
-        println!("channel open: {}", self.channel.is_open());
+        println!("conn open: {}", channel.is_connection_open());
+        println!("channel open: {}", channel.is_open());
 
         let q_args = QueueDeclareArguments::new("")
             .durable(false)
             .exclusive(true)
             .finish();
-        (self.ack_queue, _, _) = self.channel.queue_declare(q_args).await.unwrap().unwrap();
-
-        self.channel
-            .queue_bind(QueueBindArguments::new(
-                &self.ack_queue,
-                &self.ack_exchange,
-                "ack",
-            ))
-            .await
-            .unwrap();
+        (self.ack_queue, _, _) = channel
+            .queue_declare(q_args)
+            .await?
+            .ok_or("no queue declared")?;
+
+        let ack_key = self.acks.routing_key.as_deref().unwrap_or(ACK_KEY);
+        for key in std::iter::once(ack_key).chain(COMMAND_KEYS) {
+            channel
+                .queue_bind(QueueBindArguments::new(
+                    &self.ack_queue,
+                    &self.acks.name,
+                    key,
+                ))
+                .await?;
+        }
+        Ok(())
     }
 
     pub fn set_ack_channel(&mut self, ack_tx: mpsc::Sender<String>) {
         self.ack_tx = Some(ack_tx);
     }
 
+    pub fn set_cmd_channel(&mut self, cmd_tx: mpsc::Sender<(String, String)>) {
+        self.cmd_tx = Some(cmd_tx);
+    }
+
     pub fn set_alm_channel(&mut self, alm_tx: async_channel::Sender<String>) {
         self.alm_tx = Some(alm_tx);
     }
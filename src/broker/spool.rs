@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+/// Message ready to be published on the alarms exchange.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outgoing {
+    pub routing_key: String,
+    pub payload: String,
+}
+
+/// Messages not confirmed by the broker, kept on disk while it's
+/// unavailable so they survive a restart. One JSON message per line, the
+/// oldest first.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    path: PathBuf,
+}
+
+impl Spool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn load(&self) -> VecDeque<Outgoing> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return VecDeque::new(),
+            Err(e) => {
+                eprintln!("Couldn't read the spool '{}' - {e}", self.path.display());
+                return VecDeque::new();
+            }
+        };
+
+        BufReader::new(file)
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty())
+            .filter_map(|line| match serde_json::from_str(&line) {
+                Ok(message) => Some(message),
+                // A line cut short by a crash
+                Err(e) => {
+                    eprintln!("Invalid spooled message '{line}' - {e}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn append<'a>(&self, messages: impl IntoIterator<Item = &'a Outgoing>) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        Self::write(&mut file, messages)
+    }
+
+    /// Replaces the content of the spool, atomically.
+    pub fn replace<'a>(&self, messages: impl IntoIterator<Item = &'a Outgoing>) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        Self::write(&mut file, messages)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    pub fn clear(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn write<'a>(
+        file: &mut File,
+        messages: impl IntoIterator<Item = &'a Outgoing>,
+    ) -> io::Result<()> {
+        // Ends a line left incomplete by a crash, empty lines are skipped
+        let mut lines = String::from("\n");
+        for message in messages {
+            lines.push_str(&serde_json::to_string(message)?);
+            lines.push('\n');
+        }
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub fn message(key: &str) -> Outgoing {
+        Outgoing {
+            routing_key: key.to_string(),
+            payload: format!(r#"{{"name": "{key}"}}"#),
+        }
+    }
+
+    #[test]
+    fn test_spool() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("alarm_spool_{}.jsonl", std::process::id()));
+        let spool = Spool::new(&path);
+        spool.clear()?;
+        assert!(spool.load().is_empty());
+
+        let (first, second, third) = (message("a.1"), message("a.2"), message("a.3"));
+        spool.append([&first, &second])?;
+        spool.append([&third])?;
+        assert_eq!(spool.load(), [first.clone(), second.clone(), third.clone()]);
+
+        spool.replace([&second])?;
+        assert_eq!(spool.load(), [second]);
+
+        // A partly written last line is skipped
+        OpenOptions::new()
+            .append(true)
+            .open(&path)?
+            .write_all(br#"{"routing_key": "a.4", "pay"#)?;
+        assert_eq!(spool.load().len(), 1);
+        spool.append([&first])?;
+        assert_eq!(spool.load().len(), 2);
+
+        spool.clear()?;
+        assert!(spool.load().is_empty());
+        spool.clear()?;
+        Ok(())
+    }
+}
@@ -1,52 +1,300 @@
-use crate::alarm::Alarm;
+use crate::alarm::Message;
+use crate::broker::delivery::{self, Confirm, Pending, CONFIRM_TIMEOUT};
+use crate::broker::spool::{Outgoing, Spool};
+use crate::broker::{Broker, BrokerError};
+use crate::config::{ExchangeConfig, ExchangeType};
 use amqprs::{
-    channel::{BasicPublishArguments, Channel, ExchangeDeclareArguments},
-    BasicProperties,
+    callbacks::ChannelCallback,
+    channel::{BasicPublishArguments, Channel, ConfirmSelectArguments, ExchangeDeclareArguments},
+    error::Error as AmqpError,
+    Ack, BasicProperties, Cancel, CloseChannel, Nack, Return,
 };
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tokio::time::Instant;
 
-const EXCHANGE_NAME: &str = "alarms";
+/// First word of the routing keys on a topic exchange if not configured.
+const ALARM_KEY: &str = "alarm";
+const ESCALATION_KEY: &str = "escalation";
+const NOTICE_KEY: &str = "notice";
+
+/// Forwards the publisher confirms of the channel to the writer.
+struct ConfirmCallback {
+    tx: mpsc::UnboundedSender<Confirm>,
+}
+
+#[async_trait]
+impl ChannelCallback for ConfirmCallback {
+    async fn close(&mut self, _channel: &Channel, close: CloseChannel) -> Result<(), AmqpError> {
+        eprintln!("rabbitMQ closed the writer channel - {close}");
+        let _ = self.tx.send(Confirm::Closed);
+        Ok(())
+    }
+
+    async fn cancel(&mut self, _channel: &Channel, _cancel: Cancel) -> Result<(), AmqpError> {
+        Ok(())
+    }
+
+    async fn flow(&mut self, _channel: &Channel, active: bool) -> Result<bool, AmqpError> {
+        Ok(active)
+    }
+
+    async fn publish_ack(&mut self, _channel: &Channel, ack: Ack) {
+        let _ = self.tx.send(Confirm::Ack {
+            tag: ack.delivery_tag(),
+            multiple: ack.mutiple(),
+        });
+    }
+
+    async fn publish_nack(&mut self, _channel: &Channel, nack: Nack) {
+        let _ = self.tx.send(Confirm::Nack {
+            tag: nack.delivery_tag(),
+        });
+    }
+
+    async fn publish_return(
+        &mut self,
+        _channel: &Channel,
+        ret: Return,
+        _basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) {
+        let _ = self.tx.send(Confirm::Returned {
+            routing_key: ret.routing_key().to_string(),
+            payload: String::from_utf8_lossy(&content).into_owned(),
+            reply: ret.reply_text().to_string(),
+        });
+    }
+}
 
 pub struct Writer {
-    channel: Channel,
-    exchange_name: String,
-    publish_args: BasicPublishArguments,
-    rx: Option<mpsc::Receiver<Alarm>>,
+    broker: Broker,
+    channel: Option<Channel>,
+    exchange: ExchangeConfig,
+    kind: ExchangeType,
+    rx: Option<mpsc::Receiver<Message>>,
+    confirms: mpsc::UnboundedReceiver<Confirm>,
+    /// Delivery tag of the next message published on the channel.
+    next_tag: u64,
+    buffer: VecDeque<Outgoing>,
+    pending: VecDeque<Pending>,
+    spool: Spool,
+    /// The spool holds messages not confirmed yet.
+    spooled: bool,
 }
 
 impl Writer {
-    pub fn new(channel: Channel) -> Self {
+    pub fn new(broker: Broker, exchange: ExchangeConfig, spool: Spool) -> Self {
+        // No channel yet, the first publish connects
+        let (_, confirms) = mpsc::unbounded_channel();
         Self {
-            channel,
-            exchange_name: EXCHANGE_NAME.to_string(),
-            publish_args: BasicPublishArguments::new(EXCHANGE_NAME, ""),
+            broker,
+            channel: None,
+            kind: exchange.kind.unwrap_or(ExchangeType::Topic),
+            exchange,
             rx: None,
+            confirms,
+            next_tag: 1,
+            buffer: VecDeque::new(),
+            pending: VecDeque::new(),
+            spool,
+            spooled: false,
         }
     }
 
-    pub async fn connect(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let x_type = "direct";
-        let x_args = ExchangeDeclareArguments::new(&self.exchange_name, x_type)
+    /// Opens a channel in confirm mode and declares the exchange.
+    pub async fn connect(&mut self) -> Result<(), BrokerError> {
+        let (tx, confirms) = mpsc::unbounded_channel();
+        let channel = self.broker.open_channel(ConfirmCallback { tx }).await?;
+        channel
+            .confirm_select(ConfirmSelectArguments::default())
+            .await?;
+
+        let x_type = self.kind.as_str();
+        let x_args = ExchangeDeclareArguments::new(&self.exchange.name, x_type)
             .durable(true)
             .finish();
-        self.channel.exchange_declare(x_args).await?;
+        channel.exchange_declare(x_args).await?;
+
+        self.channel = Some(channel);
+        self.confirms = confirms;
+        self.next_tag = 1;
         Ok(())
     }
 
+    /// Publishes the events in order, each one until the broker confirms
+    /// it. While the broker is unavailable they're spooled to disk and
+    /// published once the connection is back.
     pub async fn write(&mut self) {
-        while let Some(alm) = self.rx.as_mut().unwrap().recv().await {
-            self.channel
-                .basic_publish(
-                    BasicProperties::default(),
-                    serde_json::to_string(&alm).unwrap().into_bytes(),
-                    self.publish_args.clone(),
-                )
-                .await
-                .unwrap();
+        let Some(mut rx) = self.rx.take() else {
+            eprintln!("Writer started without a channel");
+            return;
+        };
+
+        // Events left by the previous run go first
+        self.buffer = self.spool.load();
+        self.spooled = !self.buffer.is_empty();
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            if let Err(e) = self.flush().await {
+                eprintln!("Error publishing to rabbitMQ - {e}");
+                self.recover(&mut rx).await;
+                continue;
+            }
+
+            tokio::select! {
+                alm = rx.recv() => match alm {
+                    Some(alm) => {
+                        if let Some(message) = self.outgoing(alm) {
+                            self.buffer.push_back(message);
+                        }
+                    }
+                    None => return,
+                },
+                confirm = self.confirms.recv() => {
+                    if let Err(e) = self.confirm(confirm.unwrap_or(Confirm::Closed)) {
+                        eprintln!("Unconfirmed messages on rabbitMQ - {e}");
+                        self.recover(&mut rx).await;
+                    }
+                },
+                _ = interval.tick() => {
+                    if delivery::is_late(&self.pending) {
+                        eprintln!("rabbitMQ didn't confirm the messages in {CONFIRM_TIMEOUT:?}");
+                        self.recover(&mut rx).await;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Publishes the buffered messages.
+    async fn flush(&mut self) -> Result<(), BrokerError> {
+        while let Some(message) = self.buffer.pop_front() {
+            if let Err(e) = self.publish(&message).await {
+                self.buffer.push_front(message);
+                return Err(e);
+            }
+            self.pending.push_back(Pending {
+                tag: self.next_tag,
+                sent: Instant::now(),
+                message,
+            });
+            self.next_tag += 1;
+        }
+        Ok(())
+    }
+
+    async fn publish(&self, message: &Outgoing) -> Result<(), BrokerError> {
+        let channel = self.channel.as_ref().ok_or("writer not connected")?;
+        // Unroutable messages are returned, to be logged
+        let args = BasicPublishArguments::new(&self.exchange.name, &message.routing_key)
+            .mandatory(true)
+            .finish();
+        channel
+            .basic_publish(
+                BasicProperties::default().with_persistence(true).finish(),
+                message.payload.clone().into_bytes(),
+                args,
+            )
+            .await?;
+        Ok(())
+    }
+
+    fn confirm(&mut self, confirm: Confirm) -> Result<(), BrokerError> {
+        delivery::confirm(&mut self.pending, confirm)?;
+
+        // The spool only keeps what the broker didn't confirm yet
+        if self.spooled {
+            self.spooled = delivery::respool(&self.spool, &self.pending, &self.buffer);
+        }
+        Ok(())
+    }
+
+    /// Spools the messages not confirmed and connects again, spooling the
+    /// events received in the meantime. Then replays the spool in order.
+    async fn recover(&mut self, rx: &mut mpsc::Receiver<Message>) {
+        self.channel = None;
+
+        // Published but unconfirmed messages go before the buffered ones
+        if delivery::stash(&self.spool, &mut self.pending, &mut self.buffer) {
+            self.spooled = true;
+        }
+
+        let broker = self.broker.clone();
+        loop {
+            {
+                let reconnect = broker.reconnect();
+                tokio::pin!(reconnect);
+                loop {
+                    tokio::select! {
+                        _ = &mut reconnect => break,
+                        Some(alm) = rx.recv() => self.spool_event(alm),
+                    }
+                }
+            }
+
+            match self.connect().await {
+                Ok(()) => break,
+                Err(e) => {
+                    eprintln!("Couldn't set the writer up again - {e}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+
+        if self.spooled {
+            delivery::replay(&self.spool, &mut self.buffer);
+        }
+    }
+
+    fn spool_event(&mut self, alm: Message) {
+        let Some(message) = self.outgoing(alm) else {
+            return;
+        };
+        if delivery::spool_message(&self.spool, &mut self.buffer, message) {
+            self.spooled = true;
+        }
+    }
+
+    /// Message to publish for the event, if any.
+    fn outgoing(&self, alm: Message) -> Option<Outgoing> {
+        match serde_json::to_string(&alm) {
+            Ok(payload) => Some(Outgoing {
+                routing_key: self.routing_key(&alm),
+                payload,
+            }),
+            Err(e) => {
+                eprintln!("Error serializing {alm:?} - {e}");
+                None
+            }
+        }
+    }
+
+    /// Routing key of the message. On a topic exchange the alarms are
+    /// published as `alarm.sub1.alarm1.high`, the escalations as
+    /// `escalation.sub1.alarm1.high` and the notices as `notice.flood_start`.
+    fn routing_key(&self, alm: &Message) -> String {
+        if self.kind != ExchangeType::Topic {
+            return match alm {
+                Message::Escalated(_) => ESCALATION_KEY.to_string(),
+                _ => self.exchange.routing_key.clone().unwrap_or_default(),
+            };
+        }
+
+        match alm {
+            Message::Alarm(event) => {
+                let prefix = self.exchange.routing_key.as_deref().unwrap_or(ALARM_KEY);
+                format!("{prefix}.{}", event.topic())
+            }
+            Message::Escalated(event) => format!("{ESCALATION_KEY}.{}", event.topic()),
+            Message::Notice(notice) => format!("{NOTICE_KEY}.{}", notice.event()),
         }
     }
 
-    pub fn set_channel(&mut self, rx: mpsc::Receiver<Alarm>) {
+    pub fn set_channel(&mut self, rx: mpsc::Receiver<Message>) {
         self.rx = Some(rx);
     }
 }
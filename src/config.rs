@@ -1,12 +1,18 @@
+use crate::alarm::AlarmSeverity;
 use serde::Deserialize;
 use std::fs;
+use std::time::Duration;
 use toml;
 
 pub fn read_config(path: &str) -> Config {
     let source =
         fs::read_to_string(path).expect(&format!("config file not found. Path: '{}'", path));
 
-    toml::from_str(&source).expect("Invalid configuration file")
+    let config: Config = toml::from_str(&source).expect("Invalid configuration file");
+    if let Some(tls) = &config.broker.tls {
+        tls.check().expect("Invalid broker TLS configuration");
+    }
+    config
 }
 
 #[derive(Deserialize, Default)]
@@ -18,7 +24,7 @@ pub struct Config {
     pub db: DBConfig,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct BrokerConfig {
     #[serde(default = "default_ip")]
     pub ip: String,
@@ -31,6 +37,101 @@ pub struct BrokerConfig {
 
     #[serde(default = "default_cred")]
     pub password: String,
+
+    #[serde(default = "default_vhost")]
+    pub vhost: String,
+
+    /// Exchange the triggers are read from.
+    #[serde(default = "default_trigger_exchange")]
+    pub triggers: ExchangeConfig,
+
+    /// Exchange the acks and operator commands are read from.
+    #[serde(default = "default_ack_exchange")]
+    pub acks: ExchangeConfig,
+
+    /// Exchange the alarm events are published to.
+    #[serde(default = "default_alarm_exchange")]
+    pub alarms: ExchangeConfig,
+
+    /// File the alarm events are kept in while the broker is unavailable.
+    #[serde(default = "default_spool")]
+    pub spool: String,
+
+    /// Connect over AMQPS, plain text if missing.
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ExchangeConfig {
+    pub name: String,
+
+    /// Direct if missing, topic for the alarms.
+    #[serde(rename = "type", default)]
+    pub kind: Option<ExchangeType>,
+
+    /// Key the queue is bound with, a pattern like `plant1.#` on topic
+    /// exchanges. Without it the triggers use an empty key and the acks
+    /// `ack`. For the alarms it's the first word of their routing keys on
+    /// a topic exchange, `alarm` if missing, and the whole key otherwise.
+    #[serde(default)]
+    pub routing_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExchangeType {
+    #[default]
+    Direct,
+    Topic,
+    Fanout,
+}
+
+impl ExchangeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExchangeType::Direct => "direct",
+            ExchangeType::Topic => "topic",
+            ExchangeType::Fanout => "fanout",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// CA bundle the server certificate is checked against. The system
+    /// roots are used if missing.
+    #[serde(default)]
+    pub ca: Option<String>,
+
+    /// Client certificate and its private key, both in PEM.
+    #[serde(default)]
+    pub cert: Option<String>,
+
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// Name checked against the server certificate, the broker ip if
+    /// missing.
+    #[serde(default)]
+    pub server_name: Option<String>,
+
+    /// Authenticate with the client certificate (EXTERNAL) instead of the
+    /// username and password.
+    #[serde(default)]
+    pub external_auth: bool,
+}
+
+impl TlsConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.cert.is_some() != self.key.is_some() {
+            return Err("the client certificate and key must be set together".to_string());
+        }
+        if self.external_auth && self.cert.is_none() {
+            return Err("EXTERNAL authentication needs a client certificate".to_string());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize)]
@@ -46,6 +147,57 @@ pub struct ServerConfig {
 pub struct AlarmConfig {
     #[serde(default = "default_path")]
     pub path: String,
+
+    /// File with the state based suppression rules.
+    #[serde(default)]
+    pub suppression: Option<String>,
+
+    /// Flood detection, disabled if missing.
+    #[serde(default)]
+    pub flood: Option<FloodConfig>,
+
+    /// Escalation of the unacknowledged alarms, per severity.
+    #[serde(default)]
+    pub escalation: Vec<EscalationPolicy>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct FloodConfig {
+    /// Alarms raised in an area within the window that start a flood.
+    pub threshold: usize,
+
+    /// Window in seconds.
+    #[serde(default = "default_flood_window")]
+    pub window: f64,
+
+    /// Hold back the low severity alarms until the flood clears.
+    #[serde(default)]
+    pub hold_low: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EscalationPolicy {
+    pub severity: AlarmSeverity,
+
+    /// Seconds an alarm can stay unacknowledged before being escalated.
+    pub after: u64,
+
+    /// Severity the alarm is raised to. Without it the alarm is only
+    /// annunciated again on the escalation routing key.
+    #[serde(default)]
+    pub raise_to: Option<AlarmSeverity>,
+}
+
+impl EscalationPolicy {
+    pub fn after(&self) -> Duration {
+        Duration::from_secs(self.after)
+    }
+}
+
+impl FloodConfig {
+    pub fn window(&self) -> Duration {
+        Duration::try_from_secs_f64(self.window.max(0.0)).unwrap_or(Duration::MAX)
+    }
 }
 
 #[derive(Deserialize)]
@@ -61,6 +213,9 @@ impl Default for AlarmConfig {
     fn default() -> Self {
         Self {
             path: default_path(),
+            suppression: None,
+            flood: None,
+            escalation: Vec::new(),
         }
     }
 }
@@ -81,6 +236,12 @@ impl Default for BrokerConfig {
             port: default_port::<5672>(),
             username: default_cred(),
             password: default_cred(),
+            vhost: default_vhost(),
+            triggers: default_trigger_exchange(),
+            acks: default_ack_exchange(),
+            alarms: default_alarm_exchange(),
+            spool: default_spool(),
+            tls: None,
         }
     }
 }
@@ -102,10 +263,42 @@ fn default_cred() -> String {
     "guest".to_string()
 }
 
+fn default_vhost() -> String {
+    "/".to_string()
+}
+
+fn default_exchange(name: &str) -> ExchangeConfig {
+    ExchangeConfig {
+        name: name.to_string(),
+        kind: None,
+        routing_key: None,
+    }
+}
+
+fn default_trigger_exchange() -> ExchangeConfig {
+    default_exchange("alm_trg_exchange")
+}
+
+fn default_ack_exchange() -> ExchangeConfig {
+    default_exchange("ack_exchange")
+}
+
+fn default_alarm_exchange() -> ExchangeConfig {
+    default_exchange("alarms")
+}
+
+fn default_spool() -> String {
+    "alarm_spool.jsonl".to_string()
+}
+
 fn default_path() -> String {
     "examples/config.yaml".to_string()
 }
 
+fn default_flood_window() -> f64 {
+    600.0
+}
+
 fn default_url() -> String {
     "http://localhost:9000".to_string()
 }
@@ -128,6 +321,19 @@ mod tests {
         let config = read_config("examples/server_config.toml");
 
         assert_eq!(config.alarm.path, "examples/config.yaml");
+        assert_eq!(
+            config.alarm.suppression.as_deref(),
+            Some("examples/suppression.yaml")
+        );
+        let flood = config.alarm.flood.unwrap();
+        assert_eq!(flood.threshold, 10);
+        assert_eq!(flood.window(), Duration::from_secs(600));
+        assert!(flood.hold_low);
+        assert_eq!(config.alarm.escalation.len(), 2);
+        assert_eq!(config.alarm.escalation[0].severity, AlarmSeverity::High);
+        assert_eq!(config.alarm.escalation[0].after(), Duration::from_secs(300));
+        assert!(config.alarm.escalation[0].raise_to.is_none());
+        assert_eq!(config.alarm.escalation[1].raise_to, Some(AlarmSeverity::High));
 
         assert_eq!(config.server.ip, "127.0.0.1");
         assert_eq!(config.server.port, 8080);
@@ -136,6 +342,8 @@ mod tests {
         assert_eq!(config.broker.port, 5672);
         assert_eq!(config.broker.username, "guest");
         assert_eq!(config.broker.password, "guest");
+        assert_eq!(config.broker.acks.name, "ack_exchange");
+        assert_eq!(config.broker.acks.routing_key.as_deref(), Some("ack"));
 
         Ok(())
     }
@@ -145,6 +353,8 @@ mod tests {
         let config: Config = toml::from_str("").expect("Invalid configuration file");
 
         assert_eq!(config.alarm.path, "examples/config.yaml");
+        assert!(config.alarm.flood.is_none());
+        assert!(config.alarm.escalation.is_empty());
 
         assert_eq!(config.server.ip, "127.0.0.1");
         assert_eq!(config.server.port, 8080);
@@ -153,6 +363,78 @@ mod tests {
         assert_eq!(config.broker.port, 5672);
         assert_eq!(config.broker.username, "guest");
         assert_eq!(config.broker.password, "guest");
+        assert!(config.broker.tls.is_none());
+        assert_eq!(config.broker.vhost, "/");
+        assert_eq!(config.broker.triggers.name, "alm_trg_exchange");
+        assert_eq!(config.broker.acks.name, "ack_exchange");
+        assert_eq!(config.broker.alarms.name, "alarms");
+        assert_eq!(config.broker.spool, "alarm_spool.jsonl");
+        assert!(config.broker.alarms.kind.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exchanges() -> Result<(), Box<dyn std::error::Error>> {
+        let config = r#"
+            [broker]
+            vhost = "plants"
+
+            [broker.triggers]
+            name = "plant1.triggers"
+            type = "topic"
+            routing_key = "plant1.#"
+
+            [broker.alarms]
+            name = "plant1.alarms"
+        "#;
+
+        let config: Config = toml::from_str(config)?;
+        assert_eq!(config.broker.vhost, "plants");
+        assert_eq!(config.broker.triggers.name, "plant1.triggers");
+        assert_eq!(config.broker.triggers.kind, Some(ExchangeType::Topic));
+        assert_eq!(config.broker.triggers.routing_key.as_deref(), Some("plant1.#"));
+        assert_eq!(config.broker.acks.name, "ack_exchange");
+        assert!(config.broker.alarms.kind.is_none());
+        assert!(config.broker.alarms.routing_key.is_none());
+
+        assert!(toml::from_str::<Config>("[broker.acks]\nname = \"acks\"\ntype = \"x\"").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tls() -> Result<(), Box<dyn std::error::Error>> {
+        let config = r#"
+            [broker]
+            port = 5671
+
+            [broker.tls]
+            ca = "certs/ca.pem"
+            cert = "certs/client.pem"
+            key = "certs/client.key"
+            server_name = "rabbitmq.local"
+            external_auth = true
+        "#;
+
+        let config: Config = toml::from_str(config)?;
+        let tls = config.broker.tls.unwrap();
+        assert_eq!(tls.ca.as_deref(), Some("certs/ca.pem"));
+        assert_eq!(tls.server_name.as_deref(), Some("rabbitmq.local"));
+        assert!(tls.external_auth);
+        assert!(tls.check().is_ok());
+
+        let tls = TlsConfig {
+            cert: Some("certs/client.pem".to_string()),
+            ..Default::default()
+        };
+        assert!(tls.check().is_err());
+
+        let tls = TlsConfig {
+            external_auth: true,
+            ..Default::default()
+        };
+        assert!(tls.check().is_err());
 
         Ok(())
     }
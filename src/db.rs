@@ -1,8 +1,22 @@
-use crate::alarm::{AlarmAck, AlarmState, Alarm};
+use crate::alarm::{AckRequest, AlarmAck, AlarmState, Alarm, AlarmEvent, IsaState, StateMachine};
 use crate::config::DBConfig;
 use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 use reqwest::{Client, Url, Response, Error};
 
+/// Last state of an alarm as stored in the DB.
+#[derive(Debug)]
+pub struct StoredState {
+    pub name: String,
+    pub machine: StateMachine,
+    pub shelved_until: Option<DateTime<Utc>>,
+    pub severity: Option<String>,
+    /// When the alarm last became unacknowledged.
+    pub unack_since: Option<DateTime<Utc>>,
+    pub escalated: bool,
+    pub value: Option<f64>,
+}
+
 #[derive(Clone, Debug)]
 pub struct DB {
     url: String,
@@ -19,21 +33,33 @@ impl DB {
         }
     }
 
-    pub async fn send_ack(&self, name: &str) {
+    pub async fn send_ack(&self, ack: &AckRequest, isa_state: IsaState) {
+        let name = &ack.alarm;
         println!("Insert ack to {name}");
         let now: DateTime<Utc> = Utc::now();
 
         let timestamp = now.to_rfc3339();
         let table = &self.table;
+        let user = Self::text(ack.user.as_deref());
+        let comment = Self::text(ack.comment.as_deref());
+        let client_timestamp = Self::symbol(ack.timestamp.map(|t| t.to_rfc3339()));
 
         let query = format! {"insert into {table} \
+        (timestamp, name, state, value, measurement, severity, ack, alarm_limit, isa_state, \
+        username, comment, client_timestamp) \
         select \
         '{timestamp}' timestamp, \
         '{name}' name, \
         state, \
         value, \
+        measurement, \
         severity, \
-        true \
+        true, \
+        alarm_limit, \
+        '{isa_state}' isa_state, \
+        {user} username, \
+        {comment} comment, \
+        {client_timestamp} client_timestamp \
         from {table} \
         where name = '{name}' \
         limit -1;"};
@@ -44,30 +70,65 @@ impl DB {
             .await;
     }
 
-    pub async fn insert_alm(&self, alm: Alarm) {
-        println!("insert state: {alm:?}");
+    pub async fn insert_alm(&self, event: AlarmEvent) {
+        println!("insert state: {event:?}");
 
+        let query = Self::insert_query(&self.table, event);
+        let resp = self
+            .client
+            .get(Self::build_full_url(&self.url, &query))
+            .send()
+            .await;
+        let _ = Self::get_body(resp).await;
+    }
+
+    fn insert_query(table: &str, event: AlarmEvent) -> String {
+        let alm = event.alarm;
         let timestamp = alm.timestamp.to_rfc3339();
-        let table = &self.table;
         let name = alm.name;
         let state = alm.state;
         let value = alm.value;
+        let measurement = event
+            .measurement
+            .filter(|m| m.is_finite())
+            .map_or("NULL".to_string(), |m| m.to_string());
         let severity = alm.severity;
         let ack = alm.ack == AlarmAck::Ack;
+        let limit = Self::symbol(event.limit);
+        let isa_state = Self::symbol(event.isa_state);
+        let shelved_until = Self::symbol(event.shelved_until.map(|t| t.to_rfc3339()));
+        let user = Self::text(event.user.as_deref());
+        let reason = Self::text(event.reason.as_deref());
+        let comment = Self::text(event.comment.as_deref());
+        let client_timestamp = Self::symbol(event.client_timestamp.map(|t| t.to_rfc3339()));
+        let suppressed = event.suppressed;
+        let escalated = event.escalated;
+        let escalated_to = Self::symbol(event.escalated_to);
 
-        let query = format! {"INSERT INTO {table} VALUES (\
+        // Columns are listed since tables of older versions got the newer
+        // ones appended in a different order
+        format! {"INSERT INTO {table} \
+        (timestamp, name, state, value, measurement, severity, ack, alarm_limit, isa_state, \
+        shelved_until, username, reason, comment, client_timestamp, suppressed, escalated, \
+        escalated_to) \
+        VALUES (\
         '{timestamp}',\
         '{name}',\
         '{state}',\
         {value},\
+        {measurement},\
         '{severity}',\
-        {ack});"};
-        let resp = self
-            .client
-            .get(Self::build_full_url(&self.url, &query))
-            .send()
-            .await;
-        let _ = Self::get_body(resp).await;
+        {ack},\
+        {limit},\
+        {isa_state},\
+        {shelved_until},\
+        {user},\
+        {reason},\
+        {comment},\
+        {client_timestamp},\
+        {suppressed},\
+        {escalated},\
+        {escalated_to});"}
     }
 
     pub async fn get_latest_alm(&self, name: String) -> Option<Alarm> {
@@ -101,7 +162,7 @@ impl DB {
         Some(Alarm {
             timestamp: Utc::now(),
             name: data[0].to_string(),
-            state: if data[1].is_boolean() && data[1].as_bool().unwrap() {
+            state: if data[1].as_str() == Some(AlarmState::Set.to_string().as_str()) {
                 AlarmState::Set
             } else {
                 AlarmState::Reset
@@ -116,6 +177,99 @@ impl DB {
         })
     }
 
+    /// State of every alarm, read from its latest row.
+    pub async fn get_latest_states(&self) -> Vec<StoredState> {
+        let table = &self.table;
+        let query = format! {
+        "SELECT name, isa_state, state, shelved_until, severity, timestamp, value, measurement, \
+        escalated \
+        FROM {table} \
+        LATEST ON timestamp PARTITION BY name;"};
+        let rows = self.dataset(&query).await;
+
+        // The escalations and the changes of state that don't come from
+        // the condition are stored after the row that activated the alarm
+        let query = format! {
+        "SELECT name, timestamp FROM {table} \
+        WHERE isa_state = 'unack' AND escalated = false \
+        LATEST ON timestamp PARTITION BY name;"};
+        let activations = self.dataset(&query).await;
+        let activations: HashMap<&str, DateTime<Utc>> = activations
+            .iter()
+            .filter_map(|row| Some((row[0].as_str()?, Self::timestamp(&row[1])?)))
+            .collect();
+
+        rows.iter()
+            .filter_map(|row| Self::stored_state(row, &activations))
+            .collect()
+    }
+
+    /// State of an alarm read from its latest row, `activations` being when
+    /// the alarms last became unacknowledged.
+    fn stored_state(
+        row: &serde_json::Value,
+        activations: &HashMap<&str, DateTime<Utc>>,
+    ) -> Option<StoredState> {
+        let name = row[0].as_str()?;
+        // Rows written before the state machine existed have no state
+        let isa_state: IsaState = serde_json::from_value(row[1].clone()).ok()?;
+        let active = match isa_state {
+            IsaState::Unack | IsaState::Acked => true,
+            IsaState::Normal | IsaState::RtnUnack => false,
+            _ => row[2].as_str() == Some(AlarmState::Set.to_string().as_str()),
+        };
+        let shelved_until = Self::timestamp(&row[3]).filter(|_| isa_state == IsaState::Shelved);
+        Some(StoredState {
+            name: name.to_string(),
+            machine: StateMachine::new(isa_state, active),
+            shelved_until,
+            severity: row[4].as_str().map(str::to_string),
+            unack_since: activations
+                .get(name)
+                .copied()
+                .or_else(|| Self::timestamp(&row[5]))
+                .filter(|_| isa_state == IsaState::Unack),
+            escalated: row[8].as_bool().unwrap_or_default(),
+            // Rows written before the measurement was stored only have the
+            // rounded value
+            value: row[7].as_f64().or(row[6].as_f64()),
+        })
+    }
+
+    /// Rows returned by the query, empty on error.
+    async fn dataset(&self, query: &str) -> Vec<serde_json::Value> {
+        let resp = self
+            .client
+            .get(Self::build_full_url(&self.url, query))
+            .send()
+            .await;
+
+        let body = match Self::get_body(resp).await {
+            Some(body) => body,
+            None => return Vec::new(),
+        };
+
+        let mut json: serde_json::Value = match serde_json::from_str(&body) {
+            Ok(j) => j,
+            Err(e) => {
+                eprintln!("Error parsing the body. body: {body} - Error: {e}");
+                return Vec::new();
+            }
+        };
+
+        match json["dataset"].take() {
+            serde_json::Value::Array(rows) => rows,
+            _ => Vec::new(),
+        }
+    }
+
+    fn timestamp(value: &serde_json::Value) -> Option<DateTime<Utc>> {
+        value
+            .as_str()
+            .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            .map(|t| t.with_timezone(&Utc))
+    }
+
     async fn get_body(req: Result<Response, Error>) -> Option<String>{
         let resp = match req {
             Ok(r) => r,
@@ -157,9 +311,20 @@ impl DB {
             timestamp TIMESTAMP,\
             name SYMBOL,\
             state SYMBOL,\
-            value SHORT,\
+            value LONG,\
+            measurement DOUBLE,\
             severity SYMBOL,\
-            ack BOOLEAN\
+            ack BOOLEAN,\
+            alarm_limit SYMBOL,\
+            isa_state SYMBOL,\
+            shelved_until TIMESTAMP,\
+            username STRING,\
+            reason STRING,\
+            comment STRING,\
+            client_timestamp TIMESTAMP,\
+            suppressed BOOLEAN,\
+            escalated BOOLEAN,\
+            escalated_to SYMBOL\
           ) timestamp (timestamp) PARTITION BY MONTH WAL \
           DEDUP UPSERT KEYS (timestamp, name);"
         );
@@ -178,5 +343,114 @@ impl DB {
                 eprintln!("error: {e}");
             }
         };
+
+        // Tables created by older versions of the server miss the newer columns
+        self.add_column("alarm_limit", "SYMBOL").await;
+        self.add_column("isa_state", "SYMBOL").await;
+        self.add_column("shelved_until", "TIMESTAMP").await;
+        self.add_column("username", "STRING").await;
+        self.add_column("reason", "STRING").await;
+        self.add_column("comment", "STRING").await;
+        self.add_column("client_timestamp", "TIMESTAMP").await;
+        self.add_column("suppressed", "BOOLEAN").await;
+        self.add_column("escalated", "BOOLEAN").await;
+        self.add_column("measurement", "DOUBLE").await;
+        self.add_column("escalated_to", "SYMBOL").await;
+        // Older tables stored the value as a SHORT, too small for most
+        // measurements
+        self.alter_column("value", "LONG").await;
+    }
+
+    async fn add_column(&self, column: &str, column_type: &str) {
+        let table = &self.table;
+        let query = format!("ALTER TABLE '{table}' ADD COLUMN IF NOT EXISTS {column} {column_type};");
+        let resp = self
+            .client
+            .get(Self::build_full_url(&self.url, &query))
+            .send()
+            .await;
+        let _ = Self::get_body(resp).await;
+    }
+
+    async fn alter_column(&self, column: &str, column_type: &str) {
+        let table = &self.table;
+        let query = format!("ALTER TABLE '{table}' ALTER COLUMN {column} TYPE {column_type};");
+        let resp = self
+            .client
+            .get(Self::build_full_url(&self.url, &query))
+            .send()
+            .await;
+        let _ = Self::get_body(resp).await;
+    }
+
+    /// Formats an optional text as a quoted SQL string or `NULL`.
+    fn text(value: Option<&str>) -> String {
+        Self::symbol(value.map(|v| v.replace('\'', "''")))
+    }
+
+    /// Formats an optional value as a quoted SQL literal or `NULL`.
+    fn symbol<T: std::fmt::Display>(value: Option<T>) -> String {
+        match value {
+            Some(v) => format!("'{v}'"),
+            None => "NULL".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alarm::{AlarmSeverity, RuntimeStore};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_restore_escalated() {
+        let low = AlarmSeverity::Low.to_string();
+        let high = AlarmSeverity::High.to_string();
+        let timestamp = Utc::now();
+        let alarm = Alarm {
+            name: "sub1/alarm1".to_string(),
+            timestamp,
+            value: 1,
+            state: AlarmState::Set,
+            severity: AlarmSeverity::Low,
+            ack: AlarmAck::NotAck,
+        };
+        let escalation = AlarmEvent {
+            isa_state: Some(IsaState::Unack),
+            escalated: true,
+            escalated_to: Some(AlarmSeverity::High),
+            ..alarm.into()
+        };
+
+        // The row keeps the severity of the alarm
+        let query = DB::insert_query("alarms", escalation);
+        assert!(query.contains(&format!("'{low}',false")));
+        assert!(query.ends_with(&format!("true,'{high}');")));
+
+        let row = json!([
+            "sub1/alarm1",
+            "unack",
+            AlarmState::Set.to_string(),
+            null,
+            low,
+            timestamp.to_rfc3339(),
+            1,
+            1.0,
+            true
+        ]);
+        let activated = timestamp - chrono::Duration::seconds(400);
+        let activations = HashMap::from([("sub1/alarm1", activated)]);
+        let stored = DB::stored_state(&row, &activations).unwrap();
+        assert_eq!(stored.severity.as_deref(), Some(low.as_str()));
+        assert_eq!(stored.unack_since, Some(activated));
+        assert!(stored.escalated);
+
+        let runtime = RuntimeStore::new();
+        runtime.restore_states(vec![stored]).await;
+        let alarms = runtime.lock().await;
+        let restored = &alarms["sub1/alarm1"];
+        assert!(restored.severity_is(&AlarmSeverity::Low));
+        assert!(restored.escalated);
     }
 }
@@ -1,4 +1,11 @@
-use alarm_server::{alarm::{self, AlarmHandler}, broker::Broker, config, db};
+use alarm_server::{
+    alarm::{
+        self, AlarmHandler, ChatterMonitor, Definitions, Emitter, Escalation, Flood, FloodMonitor,
+        Latching, Maintenance, RuntimeStore, Shelving, Suppression, Watchdog,
+    },
+    broker::Broker,
+    config, db,
+};
 use tokio::sync::mpsc;
 
 #[tokio::main]
@@ -18,24 +25,33 @@ async fn run(config: config::Config) {
     let (alm_tx, alm_rx) = mpsc::channel(100);
     let (trg_tx, trg_rx) = async_channel::bounded(100);
 
-    let mut broker = Broker::new(config.broker);
-    if let Err(e) = broker.connect().await {
-        eprint!("Couldn't connect to rabbitMQ, {e}");
-        return;
-    }
+    // Keeps retrying until rabbitMQ is up
+    let broker = Broker::new(config.broker);
+    broker.reconnect().await;
 
-    let mut reader = broker.create_reader().await.unwrap();
-    let _ = reader.connect().await;
+    // The reader and writer set themselves up again on their first failure
+    let mut reader = broker.create_reader();
+    if let Err(e) = reader.connect().await {
+        eprintln!("Couldn't set the reader up, {e}");
+    }
     reader.set_alm_channel(trg_tx);
 
-    let mut writer = broker.create_writer().await.unwrap();
-    let _ = writer.connect().await;
+    let mut writer = broker.create_writer();
+    if let Err(e) = writer.connect().await {
+        eprintln!("Couldn't set the writer up, {e}");
+    }
     writer.set_channel(alm_rx);
 
     let db = db::DB::new(config.db);
     db.try_create_table().await;
 
     let cache = cache::Cache::new().await;
+    let mut definitions = Definitions::load(&config.alarm.path);
+    if let Some(path) = &config.alarm.suppression {
+        definitions.set_suppression(Suppression::load(path));
+    }
+    let runtime = RuntimeStore::with_flood(Flood::new(config.alarm.flood.clone()));
+    runtime.restore(&db).await;
 
     let mut tasks: Vec<tokio::task::JoinHandle<_>> = Vec::new();
 
@@ -44,7 +60,9 @@ async fn run(config: config::Config) {
             trg_rx.clone(),
             alm_tx.clone(),
             db.clone(),
-            cache.clone()
+            cache.clone(),
+            definitions.clone(),
+            runtime.clone(),
         );
 
         tasks.push(tokio::spawn(async move {
@@ -53,9 +71,52 @@ async fn run(config: config::Config) {
     }
 
 
+    let watchdog = Watchdog::new(
+        alm_tx.clone(),
+        db.clone(),
+        definitions.clone(),
+        runtime.clone(),
+    );
+    tokio::spawn(async move {
+        watchdog.run().await;
+    });
+
+    let emitter = Emitter {
+        tx_publisher: alm_tx.clone(),
+        db: db.clone(),
+        runtime: runtime.clone(),
+        definitions: definitions.clone(),
+    };
+    let shelving = Shelving::new(emitter.clone());
+    let maintenance = Maintenance::new(emitter.clone());
+    let latching = Latching::new(emitter.clone());
+    let chatter = ChatterMonitor::new(emitter.clone());
+    let flood = FloodMonitor::new(emitter.clone());
+    tokio::spawn(async move {
+        flood.run().await;
+    });
+    let escalation = Escalation::new(emitter.clone(), config.alarm.escalation.clone());
+    tokio::spawn(async move {
+        escalation.run().await;
+    });
+    let settle = chatter.clone();
+    tokio::spawn(async move {
+        settle.run().await;
+    });
+    let expiry = shelving.clone();
+    tokio::spawn(async move {
+        expiry.run().await;
+    });
+
+    let (cmd_tx, cmd_rx) = mpsc::channel(100);
+    tokio::spawn(async move {
+        alarm::process_commands(cmd_rx, shelving, maintenance, latching, chatter).await;
+    });
+    reader.set_cmd_channel(cmd_tx);
+
     let (ack_tx, ack_rx) = mpsc::channel(100);
     tokio::spawn(async move {
-        alarm::process_ack(ack_rx, alm_tx, db).await;
+        alarm::process_ack(ack_rx, emitter, cache).await;
     });
     reader.set_ack_channel(ack_tx);
 
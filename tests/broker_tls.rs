@@ -0,0 +1,86 @@
+//! Tests of the AMQPS connection against a local RabbitMQ with self-signed
+//! certificates. They're ignored by default, to run them:
+//!
+//! 1. Create a CA, a server certificate for `localhost` and a client
+//!    certificate whose CN is `alarm-server`, e.g. with
+//!    [tls-gen](https://github.com/rabbitmq/tls-gen):
+//!    `cd tls-gen/basic && make CN=alarm-server`.
+//! 2. Enable `rabbitmq_auth_mechanism_ssl`, add the `alarm-server` user and
+//!    give it access to the `/` vhost. Then start the broker with:
+//!
+//!    ```text
+//!    listeners.ssl.default = 5671
+//!    ssl_options.cacertfile = /certs/ca_certificate.pem
+//!    ssl_options.certfile = /certs/server_certificate.pem
+//!    ssl_options.keyfile = /certs/server_key.pem
+//!    ssl_options.verify = verify_peer
+//!    ssl_options.fail_if_no_peer_cert = false
+//!    auth_mechanisms.1 = PLAIN
+//!    auth_mechanisms.2 = EXTERNAL
+//!    ssl_cert_login_from = common_name
+//!    ```
+//!
+//! 3. Point `ALARM_SERVER_TLS_CERTS` to the directory with the certificates
+//!    and run `cargo test --test broker_tls -- --ignored`.
+
+use alarm_server::broker::Broker;
+use alarm_server::config::{BrokerConfig, TlsConfig};
+use std::env;
+
+fn certs(file: &str) -> Option<String> {
+    let dir = env::var("ALARM_SERVER_TLS_CERTS").expect("ALARM_SERVER_TLS_CERTS not set");
+    Some(format!("{dir}/{file}"))
+}
+
+fn config(tls: TlsConfig) -> BrokerConfig {
+    BrokerConfig {
+        ip: "localhost".to_string(),
+        port: 5671,
+        tls: Some(tls),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_server_certificate() {
+    let broker = Broker::new(config(TlsConfig {
+        ca: certs("ca_certificate.pem"),
+        ..Default::default()
+    }));
+
+    broker.connect().await.expect("TLS connection failed");
+    let mut writer = broker.create_writer();
+    writer.connect().await.expect("Writer set up failed");
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_external_auth() {
+    let broker = Broker::new(config(TlsConfig {
+        ca: certs("ca_certificate.pem"),
+        cert: certs("client_certificate.pem"),
+        key: certs("client_key.pem"),
+        external_auth: true,
+        ..Default::default()
+    }));
+
+    broker.connect().await.expect("EXTERNAL authentication failed");
+    let mut reader = broker.create_reader();
+    reader.connect().await.expect("Reader set up failed");
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_untrusted_server() {
+    // The self-signed CA isn't among the system roots
+    let broker = Broker::new(config(TlsConfig::default()));
+    assert!(broker.connect().await.is_err());
+
+    let broker = Broker::new(config(TlsConfig {
+        ca: certs("ca_certificate.pem"),
+        server_name: Some("not-the-broker".to_string()),
+        ..Default::default()
+    }));
+    assert!(broker.connect().await.is_err());
+}